@@ -0,0 +1,19 @@
+CREATE TABLE new_queue (
+	id VARCHAR PRIMARY KEY NOT NULL,
+	status VARCHAR NOT NULL,
+	reason VARCHAR,
+	data TEXT NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	repository_id VARCHAR NOT NULL,
+	CONSTRAINT fk_repository
+		FOREIGN KEY(repository_id)
+		REFERENCES repositories(id)
+		ON DELETE CASCADE
+);
+
+INSERT INTO new_queue (id, status, reason, data, created_at, updated_at, repository_id)
+SELECT id, status, reason, data, created_at, updated_at, repository_id FROM queue;
+
+DROP TABLE queue;
+ALTER TABLE new_queue RENAME TO queue;
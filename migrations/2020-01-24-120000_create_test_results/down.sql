@@ -0,0 +1,23 @@
+DROP TABLE test_cases;
+DROP TABLE test_suites;
+
+CREATE TABLE new_repositories (
+	id VARCHAR PRIMARY KEY NOT NULL,
+	slug VARCHAR NOT NULL,
+	name VARCHAR NOT NULL,
+	run VARCHAR NOT NULL,
+	working_dir VARCHAR,
+	secret VARCHAR NOT NULL,
+	variables TEXT,
+	triggers TEXT,
+	webhooks TEXT,
+	deleted INTEGER NOT NULL DEFAULT 0,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO new_repositories (id, slug, name, run, working_dir, secret, variables, triggers, webhooks, deleted, created_at, updated_at)
+SELECT id, slug, name, run, working_dir, secret, variables, triggers, webhooks, deleted, created_at, updated_at FROM repositories;
+
+DROP TABLE repositories;
+ALTER TABLE new_repositories RENAME TO repositories;
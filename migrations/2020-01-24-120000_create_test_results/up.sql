@@ -0,0 +1,38 @@
+ALTER TABLE repositories ADD COLUMN test_reports TEXT;
+
+CREATE TABLE test_suites (
+	id VARCHAR PRIMARY KEY NOT NULL,
+	name VARCHAR NOT NULL,
+	tests INTEGER NOT NULL,
+	failures INTEGER NOT NULL,
+	errors INTEGER NOT NULL,
+	skipped INTEGER NOT NULL,
+	duration DOUBLE NOT NULL,
+	queue_id VARCHAR NOT NULL,
+	CONSTRAINT fk_queue
+		FOREIGN KEY(queue_id)
+		REFERENCES queue(id)
+		ON DELETE CASCADE
+);
+
+CREATE TABLE test_cases (
+	id VARCHAR PRIMARY KEY NOT NULL,
+	name VARCHAR NOT NULL,
+	classname VARCHAR,
+	status VARCHAR NOT NULL,
+	duration DOUBLE NOT NULL,
+	message TEXT,
+	details TEXT,
+	suite_id VARCHAR NOT NULL,
+	queue_id VARCHAR NOT NULL,
+	CONSTRAINT fk_suite
+		FOREIGN KEY(suite_id)
+		REFERENCES test_suites(id)
+		ON DELETE CASCADE,
+	CONSTRAINT fk_queue
+		FOREIGN KEY(queue_id)
+		REFERENCES queue(id)
+		ON DELETE CASCADE
+);
+
+CREATE INDEX test_cases_queue_id ON test_cases(queue_id);
@@ -0,0 +1,16 @@
+ALTER TABLE repositories ADD COLUMN caches TEXT;
+
+CREATE TABLE caches (
+	id VARCHAR PRIMARY KEY NOT NULL,
+	cache_key VARCHAR NOT NULL,
+	size BIGINT NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	last_used_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	repository_id VARCHAR NOT NULL,
+	CONSTRAINT fk_repository
+		FOREIGN KEY(repository_id)
+		REFERENCES repositories(id)
+		ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX caches_repository_key ON caches(repository_id, cache_key);
@@ -0,0 +1,14 @@
+ALTER TABLE repositories ADD COLUMN matrix TEXT;
+
+CREATE TABLE builds (
+	id VARCHAR PRIMARY KEY NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	repository_id VARCHAR NOT NULL,
+	CONSTRAINT fk_repository
+		FOREIGN KEY(repository_id)
+		REFERENCES repositories(id)
+		ON DELETE CASCADE
+);
+
+ALTER TABLE queue ADD COLUMN build_id VARCHAR REFERENCES builds(id) ON DELETE CASCADE;
+ALTER TABLE queue ADD COLUMN matrix TEXT;
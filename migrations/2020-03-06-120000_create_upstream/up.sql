@@ -0,0 +1,2 @@
+ALTER TABLE repositories ADD COLUMN upstream TEXT;
+ALTER TABLE queue ADD COLUMN upstream_job_id VARCHAR REFERENCES queue(id) ON DELETE SET NULL;
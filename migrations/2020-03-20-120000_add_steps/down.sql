@@ -0,0 +1,48 @@
+CREATE TABLE new_queue (
+	id VARCHAR PRIMARY KEY NOT NULL,
+	status VARCHAR NOT NULL,
+	reason VARCHAR,
+	data TEXT NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	repository_id VARCHAR NOT NULL,
+	outputs TEXT,
+	build_id VARCHAR REFERENCES builds(id) ON DELETE CASCADE,
+	matrix TEXT,
+	upstream_job_id VARCHAR REFERENCES queue(id) ON DELETE SET NULL,
+	CONSTRAINT fk_repository
+		FOREIGN KEY(repository_id)
+		REFERENCES repositories(id)
+		ON DELETE CASCADE
+);
+
+INSERT INTO new_queue (id, status, reason, data, created_at, updated_at, repository_id, outputs, build_id, matrix, upstream_job_id)
+SELECT id, status, reason, data, created_at, updated_at, repository_id, outputs, build_id, matrix, upstream_job_id FROM queue;
+
+DROP TABLE queue;
+ALTER TABLE new_queue RENAME TO queue;
+
+CREATE TABLE new_repositories (
+	id VARCHAR PRIMARY KEY NOT NULL,
+	slug VARCHAR NOT NULL,
+	name VARCHAR NOT NULL,
+	run VARCHAR NOT NULL,
+	working_dir VARCHAR,
+	secret VARCHAR NOT NULL,
+	variables TEXT,
+	triggers TEXT,
+	webhooks TEXT,
+	deleted INTEGER NOT NULL DEFAULT 0,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	test_reports TEXT,
+	caches TEXT,
+	matrix TEXT,
+	upstream TEXT
+);
+
+INSERT INTO new_repositories (id, slug, name, run, working_dir, secret, variables, triggers, webhooks, deleted, created_at, updated_at, test_reports, caches, matrix, upstream)
+SELECT id, slug, name, run, working_dir, secret, variables, triggers, webhooks, deleted, created_at, updated_at, test_reports, caches, matrix, upstream FROM repositories;
+
+DROP TABLE repositories;
+ALTER TABLE new_repositories RENAME TO repositories;
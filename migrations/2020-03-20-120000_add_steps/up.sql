@@ -0,0 +1,2 @@
+ALTER TABLE repositories ADD COLUMN steps TEXT;
+ALTER TABLE queue ADD COLUMN steps TEXT;
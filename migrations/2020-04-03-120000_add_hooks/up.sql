@@ -0,0 +1,3 @@
+ALTER TABLE repositories ADD COLUMN after_success TEXT;
+ALTER TABLE repositories ADD COLUMN after_failure TEXT;
+ALTER TABLE repositories ADD COLUMN always TEXT;
@@ -0,0 +1,46 @@
+CREATE TABLE new_queue_logs (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	status VARCHAR NOT NULL,
+	reason VARCHAR,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	queue_id VARCHAR NOT NULL,
+	CONSTRAINT fk_queue
+		FOREIGN KEY(queue_id)
+		REFERENCES queue(id)
+		ON DELETE CASCADE
+);
+
+INSERT INTO new_queue_logs (id, status, reason, created_at, queue_id)
+SELECT id, status, reason, created_at, queue_id FROM queue_logs;
+
+DROP TABLE queue_logs;
+ALTER TABLE new_queue_logs RENAME TO queue_logs;
+
+CREATE TABLE new_repositories (
+	id VARCHAR PRIMARY KEY NOT NULL,
+	slug VARCHAR NOT NULL,
+	name VARCHAR NOT NULL,
+	run VARCHAR NOT NULL,
+	working_dir VARCHAR,
+	secret VARCHAR NOT NULL,
+	variables TEXT,
+	triggers TEXT,
+	webhooks TEXT,
+	deleted INTEGER NOT NULL DEFAULT 0,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	test_reports TEXT,
+	caches TEXT,
+	matrix TEXT,
+	upstream TEXT,
+	steps TEXT,
+	after_success TEXT,
+	after_failure TEXT,
+	always TEXT
+);
+
+INSERT INTO new_repositories (id, slug, name, run, working_dir, secret, variables, triggers, webhooks, deleted, created_at, updated_at, test_reports, caches, matrix, upstream, steps, after_success, after_failure, always)
+SELECT id, slug, name, run, working_dir, secret, variables, triggers, webhooks, deleted, created_at, updated_at, test_reports, caches, matrix, upstream, steps, after_success, after_failure, always FROM repositories;
+
+DROP TABLE repositories;
+ALTER TABLE new_repositories RENAME TO repositories;
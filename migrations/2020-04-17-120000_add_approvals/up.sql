@@ -0,0 +1,3 @@
+ALTER TABLE repositories ADD COLUMN requires_approval INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE repositories ADD COLUMN approval_timeout INTEGER;
+ALTER TABLE queue_logs ADD COLUMN username VARCHAR;
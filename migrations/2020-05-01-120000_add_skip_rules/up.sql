@@ -0,0 +1 @@
+ALTER TABLE repositories ADD COLUMN skip_rules TEXT;
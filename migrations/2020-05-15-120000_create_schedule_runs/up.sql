@@ -0,0 +1,12 @@
+CREATE TABLE schedule_runs (
+	id VARCHAR PRIMARY KEY NOT NULL,
+	schedule_key VARCHAR NOT NULL,
+	last_run_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	repository_id VARCHAR NOT NULL,
+	CONSTRAINT fk_repository
+		FOREIGN KEY(repository_id)
+		REFERENCES repositories(id)
+		ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX schedule_runs_repository_key ON schedule_runs(repository_id, schedule_key);
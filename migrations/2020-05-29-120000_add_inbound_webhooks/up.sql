@@ -0,0 +1 @@
+ALTER TABLE repositories ADD COLUMN inbound TEXT;
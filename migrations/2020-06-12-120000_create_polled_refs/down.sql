@@ -0,0 +1 @@
+DROP TABLE polled_refs;
@@ -0,0 +1,14 @@
+CREATE TABLE polled_refs (
+	id VARCHAR PRIMARY KEY NOT NULL,
+	url VARCHAR NOT NULL,
+	ref_name VARCHAR NOT NULL,
+	sha VARCHAR NOT NULL,
+	updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	repository_id VARCHAR NOT NULL,
+	CONSTRAINT fk_repository
+		FOREIGN KEY(repository_id)
+		REFERENCES repositories(id)
+		ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX polled_refs_repository_url_ref ON polled_refs(repository_id, url, ref_name);
@@ -0,0 +1 @@
+ALTER TABLE repositories ADD COLUMN status_reporting TEXT;
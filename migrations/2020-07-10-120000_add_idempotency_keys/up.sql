@@ -0,0 +1,3 @@
+ALTER TABLE queue ADD COLUMN idempotency_key TEXT;
+CREATE UNIQUE INDEX queue_idempotency_key ON queue(repository_id, idempotency_key);
+ALTER TABLE repositories ADD COLUMN idempotency_variable TEXT;
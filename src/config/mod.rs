@@ -1,7 +1,7 @@
-use failure::Error;
+use failure::{format_err, Error};
 use secstr::SecStr;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::fs::read_to_string;
 use std::path::Path;
@@ -20,6 +20,30 @@ pub struct PersistedConfig {
 	pub port: u16,
 	#[serde(default)]
 	pub authentication_type: AuthenticationType,
+	/// Maximum size of all dependency caches in megabytes
+	#[serde(default = "default_cache_size_limit")]
+	pub cache_size_limit: u64,
+	/// Maximum number of steps of a single job which run at the same time
+	#[serde(default = "default_max_parallel_steps")]
+	pub max_parallel_steps: usize,
+	/// URL LittleCI is reached at, used to link to jobs. Defaults to the host and port.
+	#[serde(default)]
+	pub public_url: Option<String>,
+	/// Seconds during which a repeated idempotency key returns the job it first queued
+	#[serde(default = "default_idempotency_retention")]
+	pub idempotency_retention: u64,
+}
+
+fn default_cache_size_limit() -> u64 {
+	5120
+}
+
+fn default_idempotency_retention() -> u64 {
+	86400
+}
+
+fn default_max_parallel_steps() -> usize {
+	4
 }
 
 #[derive(Debug, Clone)]
@@ -31,6 +55,29 @@ pub struct AppConfig {
 	pub network_host: String,
 	pub port: u16,
 	pub authentication_type: AuthenticationType,
+	/// Maximum size of all dependency caches in bytes
+	pub cache_size_limit: u64,
+	pub max_parallel_steps: usize,
+	pub public_url: Option<String>,
+	/// Seconds during which a repeated idempotency key returns the job it first queued
+	pub idempotency_retention: u64,
+}
+
+impl AppConfig {
+	/// URL LittleCI is reached at, without a trailing slash
+	pub fn public_url(&self) -> String {
+		match &self.public_url {
+			Some(url) => url.trim_end_matches('/').to_owned(),
+			None => {
+				// LittleCI listens on all interfaces by default, which isn't an address to connect to
+				let host = match self.network_host.as_str() {
+					"0.0.0.0" => "localhost",
+					host => host,
+				};
+				format!("http://{}:{}", host, self.port)
+			}
+		}
+	}
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
@@ -51,10 +98,59 @@ pub struct Repository {
 pub enum GitTrigger {
 	#[serde(rename = "any")]
 	Any,
+	/// Branch names or patterns. Patterns starting with `!` exclude branches, patterns starting
+	/// with `^` are regular expressions and anything else is a glob, eg. `release/*`.
 	#[serde(rename = "head")]
 	Head(Vec<String>),
+	/// Every tag
 	#[serde(rename = "tag")]
 	Tag,
+	/// Tag names or patterns, in the same format as `Head`
+	#[serde(rename = "tags")]
+	Tags(Vec<String>),
+	/// Pushes which change files matching the path patterns
+	#[serde(rename = "paths")]
+	Paths(PathTrigger),
+	/// Pull requests into base branches matching the names or patterns, in the same format as
+	/// `Head`. Defaults to every branch. Pull requests only match this trigger.
+	#[serde(rename = "pull_request")]
+	PullRequest(Vec<String>),
+	/// Deleted branches or tags matching the names or patterns, in the same format as `Head`.
+	/// Defaults to every branch and tag. Deletions only match this trigger.
+	#[serde(rename = "delete")]
+	Delete(Vec<String>),
+}
+
+/// Matches pushes where any changed file matches an `include` pattern and no `exclude` pattern.
+/// Patterns are globs, eg. `services/api/**`, or regular expressions starting with `^`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PathTrigger {
+	/// Defaults to every file
+	#[serde(default)]
+	pub include: Vec<String>,
+	#[serde(default)]
+	pub exclude: Vec<String>,
+	/// Branch names or patterns the push must be to, in the same format as `GitTrigger::Head`.
+	/// Defaults to every branch.
+	#[serde(default)]
+	pub head: Vec<String>,
+}
+
+/// What to do with scheduled runs which were missed, eg. because LittleCI wasn't running.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum MissedRuns {
+	/// Wait for the next scheduled run
+	#[serde(rename = "skip")]
+	Skip,
+	/// Run once to make up for any missed runs
+	#[serde(rename = "catch_up")]
+	CatchUp,
+}
+
+impl Default for MissedRuns {
+	fn default() -> Self {
+		Self::Skip
+	}
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -63,6 +159,278 @@ pub enum Trigger {
 	Any,
 	#[serde(rename = "git")]
 	Git(GitTrigger),
+	/// Queue a job at the times matching a cron expression
+	#[serde(rename = "schedule")]
+	Schedule {
+		cron: String,
+		/// Timezone name, eg. `Europe/London`. Defaults to UTC.
+		#[serde(default)]
+		timezone: Option<String>,
+		/// Data passed to the scheduled jobs
+		#[serde(default)]
+		data: HashMap<String, String>,
+		#[serde(default)]
+		missed: MissedRuns,
+	},
+	/// Poll a git remote for branches which moved, for repositories which can't send webhooks
+	#[serde(rename = "poll")]
+	Poll {
+		/// Anything `git ls-remote` accepts, including the path of a local bare repository
+		url: String,
+		/// Patterns of the branches to watch. Defaults to all branches.
+		#[serde(default)]
+		refs: Vec<String>,
+		/// Seconds between polls
+		#[serde(default = "default_poll_interval")]
+		interval: u64,
+	},
+}
+
+fn default_poll_interval() -> u64 {
+	60
+}
+
+/// Paths within the working dir which are restored before and saved after a build.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CacheEntry {
+	/// Paths relative to the working dir
+	pub paths: Vec<String>,
+	/// Template for the cache key. Expressions inside `{{ }}` are replaced: `hash(a, b)` with a
+	/// hash of the contents of files `a` and `b`, and any other name with the value of the job
+	/// variable of that name, eg. `cargo-{{ hash(Cargo.lock) }}-{{ LITTLECI_GIT_BRANCH }}`.
+	pub key: String,
+	/// Don't save the cache if it's larger than this many megabytes
+	#[serde(default)]
+	pub max_size: Option<u64>,
+}
+
+/// A named command which runs once all of the steps it needs have completed.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Step {
+	pub name: String,
+	/// Command to execute
+	pub run: String,
+	/// Names of steps which must complete before this step is started
+	#[serde(default)]
+	pub needs: Vec<String>,
+	/// Wait for a user to approve the job before the step is started
+	#[serde(default)]
+	pub approval: bool,
+}
+
+/// Skips a job when the value of a variable in its data matches a regular expression.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SkipRule {
+	pub variable: String,
+	pub pattern: String,
+	/// Reason recorded on the skipped job
+	#[serde(default)]
+	pub reason: Option<String>,
+}
+
+/// Maps fields of any JSON sent to `/notify/<repository>/webhook` to job variables.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct InboundWebhook {
+	/// Variable names and JSON Pointers to their values, eg. `/push_data/tag`
+	#[serde(default)]
+	pub variables: BTreeMap<String, String>,
+	/// Only queue a job when every filter matches
+	#[serde(default)]
+	pub filters: Vec<InboundFilter>,
+	/// Verify a signature of the body instead of requiring the repository secret
+	#[serde(default)]
+	pub signature: Option<InboundSignature>,
+}
+
+/// Matches when the value at a JSON Pointer matches a regular expression.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct InboundFilter {
+	pub pointer: String,
+	pub pattern: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum SignatureAlgorithm {
+	#[serde(rename = "sha1")]
+	Sha1,
+	#[serde(rename = "sha256")]
+	Sha256,
+}
+
+impl Default for SignatureAlgorithm {
+	fn default() -> Self {
+		Self::Sha256
+	}
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum SignatureEncoding {
+	#[serde(rename = "hex")]
+	Hex,
+	#[serde(rename = "base64")]
+	Base64,
+}
+
+impl Default for SignatureEncoding {
+	fn default() -> Self {
+		Self::Hex
+	}
+}
+
+/// An HMAC of the request body, keyed with the repository secret.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct InboundSignature {
+	/// Name of the header containing the signature
+	pub header: String,
+	#[serde(default)]
+	pub algorithm: SignatureAlgorithm,
+	#[serde(default)]
+	pub encoding: SignatureEncoding,
+	/// Removed from the header value before decoding, eg. `sha256=`
+	#[serde(default)]
+	pub prefix: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum StatusProvider {
+	#[serde(rename = "github")]
+	GitHub,
+	#[serde(rename = "gitea")]
+	Gitea,
+	#[serde(rename = "gitlab")]
+	GitLab,
+}
+
+fn default_status_context() -> String {
+	"littleci".into()
+}
+
+/// Reports the status of jobs to the commit status API of a git forge, for the commit in
+/// `LITTLECI_GIT_AFTER`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StatusReporting {
+	pub provider: StatusProvider,
+	/// Base URL of the API, eg. `https://gitea.example.com/api/v1`. Defaults to the API of
+	/// github.com or gitlab.com, and is required for Gitea.
+	#[serde(default)]
+	pub base_url: Option<String>,
+	/// `owner/name` of the repository, or the ID or path of a GitLab project
+	pub repository: String,
+	/// Access token with permission to set commit statuses
+	pub token: String,
+	/// Name of the status shown on the commit. Jobs of a matrix add their combination.
+	#[serde(default = "default_status_context")]
+	pub context: String,
+}
+
+/// Statuses of an upstream job which trigger a downstream job.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum UpstreamStatus {
+	#[serde(rename = "completed")]
+	Completed,
+	#[serde(rename = "failed")]
+	Failed,
+	#[serde(rename = "any")]
+	Any,
+}
+
+fn default_upstream_statuses() -> Vec<UpstreamStatus> {
+	vec![UpstreamStatus::Completed]
+}
+
+/// A repository whose finished jobs trigger a job for the downstream repository.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Upstream {
+	/// Slug of the upstream repository
+	pub repository: String,
+	/// Statuses which trigger a job, defaults to `completed`
+	#[serde(default = "default_upstream_statuses")]
+	pub on: Vec<UpstreamStatus>,
+	/// Keys of the upstream job's data and outputs to pass to the downstream job
+	#[serde(default)]
+	pub pass_through: Vec<String>,
+}
+
+/// Expands a single notification into one job per combination of variable values.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Matrix {
+	/// Variable names mapped to the values each one can take
+	#[serde(default)]
+	pub variables: BTreeMap<String, Vec<String>>,
+	/// Additional combinations to run
+	#[serde(default)]
+	pub include: Vec<HashMap<String, String>>,
+	/// Combinations to remove. An entry excludes any combination which contains all of its values.
+	#[serde(default)]
+	pub exclude: Vec<HashMap<String, String>>,
+}
+
+impl Matrix {
+	/// All combinations of the matrix variables, minus excluded combinations, plus included ones.
+	pub fn expand(&self) -> Vec<HashMap<String, String>> {
+		let mut combinations: Vec<HashMap<String, String>> = Vec::new();
+
+		if !self.variables.is_empty() {
+			combinations.push(HashMap::new());
+			for (name, values) in self.variables.iter() {
+				let mut expanded = Vec::new();
+				for combination in combinations.iter() {
+					for value in values.iter() {
+						let mut combination = combination.clone();
+						combination.insert(name.clone(), value.clone());
+						expanded.push(combination);
+					}
+				}
+				combinations = expanded;
+			}
+		}
+
+		combinations.retain(|combination| {
+			!self.exclude.iter().any(|exclude| {
+				exclude
+					.iter()
+					.all(|(name, value)| combination.get(name) == Some(value))
+			})
+		});
+
+		for include in self.include.iter() {
+			if !combinations.contains(include) {
+				combinations.push(include.clone());
+			}
+		}
+
+		combinations
+	}
+
+	/// Check that every variable has values and that `include` and `exclude` entries only name
+	/// defined variables.
+	pub fn validate(&self) -> Result<(), Error> {
+		for (name, values) in self.variables.iter() {
+			if values.is_empty() {
+				return Err(format_err!("Variable `{}` has no values", name));
+			}
+		}
+
+		for (kind, entries) in [("include", &self.include), ("exclude", &self.exclude)].iter() {
+			for entry in entries.iter() {
+				if entry.is_empty() {
+					return Err(format_err!("Empty `{}` entry", kind));
+				}
+				if let Some(name) = entry
+					.keys()
+					.find(|name| !self.variables.contains_key(*name))
+				{
+					return Err(format_err!(
+						"`{}` entry names undefined variable `{}`",
+						kind,
+						name
+					));
+				}
+			}
+		}
+
+		Ok(())
+	}
 }
 
 impl Default for Trigger {
@@ -93,6 +461,9 @@ pub fn load_app_config(config_path: &str) -> Result<PersistedConfig, Error> {
 				port: 8000,
 				data_dir: Some(config_path.into()),
 				config_path: default_config_path.clone(),
+				cache_size_limit: default_cache_size_limit(),
+				max_parallel_steps: default_max_parallel_steps(),
+				idempotency_retention: default_idempotency_retention(),
 				..Default::default()
 			};
 
@@ -123,3 +494,107 @@ impl Default for AuthenticationType {
 		Self::Simple
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn matrix(json: &str) -> Matrix {
+		serde_json::from_str(json).unwrap()
+	}
+
+	fn combination(values: &[(&str, &str)]) -> HashMap<String, String> {
+		values
+			.iter()
+			.map(|(name, value)| (name.to_string(), value.to_string()))
+			.collect()
+	}
+
+	#[test]
+	fn expand_combines_every_value() {
+		let matrix =
+			matrix(r#"{"variables": {"os": ["linux", "macos"], "rust": ["stable", "nightly"]}}"#);
+
+		assert_eq!(
+			matrix.expand(),
+			vec![
+				combination(&[("os", "linux"), ("rust", "stable")]),
+				combination(&[("os", "linux"), ("rust", "nightly")]),
+				combination(&[("os", "macos"), ("rust", "stable")]),
+				combination(&[("os", "macos"), ("rust", "nightly")]),
+			]
+		);
+	}
+
+	#[test]
+	fn expand_removes_excluded_and_adds_included_combinations() {
+		let matrix = matrix(
+			r#"{
+				"variables": {"os": ["linux", "macos"], "rust": ["stable", "nightly"]},
+				"exclude": [{"os": "macos", "rust": "nightly"}],
+				"include": [{"os": "windows", "rust": "stable"}, {"os": "linux", "rust": "stable"}]
+			}"#,
+		);
+
+		assert_eq!(
+			matrix.expand(),
+			vec![
+				combination(&[("os", "linux"), ("rust", "stable")]),
+				combination(&[("os", "linux"), ("rust", "nightly")]),
+				combination(&[("os", "macos"), ("rust", "stable")]),
+				combination(&[("os", "windows"), ("rust", "stable")]),
+			]
+		);
+	}
+
+	#[test]
+	fn expand_excludes_partial_matches() {
+		let matrix = matrix(
+			r#"{"variables": {"os": ["linux", "macos"], "rust": ["stable", "nightly"]}, "exclude": [{"os": "macos"}]}"#,
+		);
+
+		assert_eq!(
+			matrix.expand(),
+			vec![
+				combination(&[("os", "linux"), ("rust", "stable")]),
+				combination(&[("os", "linux"), ("rust", "nightly")]),
+			]
+		);
+	}
+
+	#[test]
+	fn expand_without_variables_only_includes() {
+		assert!(matrix("{}").expand().is_empty());
+		assert_eq!(
+			matrix(r#"{"include": [{"os": "linux"}]}"#).expand(),
+			vec![combination(&[("os", "linux")])]
+		);
+	}
+
+	#[test]
+	fn validate_rejects_empty_and_undefined_variables() {
+		assert!(
+			matrix(r#"{"variables": {"os": ["linux"]}, "include": [{"os": "macos"}]}"#)
+				.validate()
+				.is_ok()
+		);
+
+		let error = matrix(r#"{"variables": {"os": []}}"#)
+			.validate()
+			.unwrap_err();
+		assert_eq!(error.to_string(), "Variable `os` has no values");
+
+		let error = matrix(r#"{"variables": {"os": ["linux"]}, "exclude": [{}]}"#)
+			.validate()
+			.unwrap_err();
+		assert_eq!(error.to_string(), "Empty `exclude` entry");
+
+		let error = matrix(r#"{"variables": {"os": ["linux"]}, "include": [{"arch": "arm"}]}"#)
+			.validate()
+			.unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"`include` entry names undefined variable `arch`"
+		);
+	}
+}
@@ -0,0 +1,86 @@
+use failure::{format_err, Error};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use crate::model::repositories::Repository;
+
+/// Marks hooks written by LittleCI, which may be replaced when the hook is installed again
+const HOOK_MARKER: &str = "# Installed by LittleCI";
+
+/// Quote a value for a POSIX shell
+fn shell_quote(value: &str) -> String {
+	format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// A post-receive hook which forwards the `<oldrev> <newrev> <refname>` lines git passes on
+/// stdin to `/notify/<repository>/git`. Pushes still succeed if LittleCI can't be reached.
+fn post_receive_hook(url: &str, repository: &Repository) -> String {
+	let notify_url = format!(
+		"{}/notify/{}/git",
+		url.trim_end_matches('/'),
+		&repository.slug
+	);
+
+	format!(
+		r#"#!/bin/sh
+{marker} for repository {slug}. Notifies LittleCI of pushed branches and tags.
+curl --silent --show-error --fail --max-time 10 \
+	--header "Content-Type: text/plain" \
+	--header {secret_header} \
+	--data-binary @- \
+	{url} > /dev/null || echo "Unable to notify LittleCI of this push"
+"#,
+		marker = HOOK_MARKER,
+		slug = &repository.slug,
+		secret_header = shell_quote(&format!("X-Secret-Key: {}", &repository.secret)),
+		url = shell_quote(&notify_url),
+	)
+}
+
+/// Make the hook executable. It contains the repository secret, so only its owner may read it.
+#[cfg(unix)]
+fn set_hook_permissions(hook_path: &Path) -> io::Result<()> {
+	use std::os::unix::fs::PermissionsExt;
+	fs::set_permissions(hook_path, fs::Permissions::from_mode(0o700))
+}
+
+#[cfg(not(unix))]
+fn set_hook_permissions(_hook_path: &Path) -> io::Result<()> {
+	Ok(())
+}
+
+/// Write a post-receive hook into a bare repository. Hooks which weren't written by LittleCI are
+/// left alone.
+pub fn install(
+	repository_path: &str,
+	url: &str,
+	repository: &Repository,
+) -> Result<PathBuf, Error> {
+	let hooks_dir = Path::new(repository_path).join("hooks");
+	if !hooks_dir.is_dir() {
+		return Err(format_err!(
+			"`{}` isn't a bare git repository. `hooks` directory not found",
+			repository_path
+		));
+	}
+
+	let hook_path = hooks_dir.join("post-receive");
+	if hook_path.exists() {
+		let existing = fs::read_to_string(&hook_path)?;
+		if !existing.contains(HOOK_MARKER) {
+			return Err(format_err!(
+				"`{}` already exists and wasn't installed by LittleCI",
+				hook_path.display()
+			));
+		}
+	}
+
+	fs::write(&hook_path, post_receive_hook(url, repository))?;
+	set_hook_permissions(&hook_path)?;
+
+	Ok(hook_path)
+}
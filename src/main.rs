@@ -11,7 +11,7 @@ use clap::clap_app;
 use ctrlc;
 use diesel::connection::Connection;
 use diesel::r2d2::{ConnectionManager, Pool};
-use failure::Error;
+use failure::{format_err, Error};
 use parking_lot::Mutex;
 use regex::Regex;
 use secstr::SecStr;
@@ -25,6 +25,7 @@ use std::sync::Arc;
 use std::thread;
 
 mod config;
+mod hook;
 mod model;
 mod queue;
 mod server;
@@ -32,6 +33,7 @@ mod util;
 
 use crate::config::{load_app_config, AppConfig, PersistedConfig};
 use crate::model::{DbConnectionManager, ReadConnection, WriteConnection};
+use crate::model::repositories::Repositories;
 use crate::model::users::{Users, User};
 use crate::queue::QueueManager;
 use crate::server::start_server;
@@ -113,7 +115,7 @@ pub struct AppState {
 	pub connection_manager: DbConnectionManager,
 }
 
-impl From<PersistedConfig> for AppState {
+impl From<PersistedConfig> for AppConfig {
 	fn from(configuration: PersistedConfig) -> Self {
 		let secret: String = HashedValue::new(&configuration.secret).into();
 
@@ -146,7 +148,7 @@ impl From<PersistedConfig> for AppState {
 			}
 		};
 
-		let config = AppConfig {
+		AppConfig {
 			secret: SecStr::from(secret.clone()),
 			config_path: config_path
 				.to_str()
@@ -160,33 +162,49 @@ impl From<PersistedConfig> for AppState {
 			network_host: configuration.network_host.clone(),
 			port: configuration.port,
 			authentication_type: configuration.authentication_type,
-		};
+			cache_size_limit: configuration.cache_size_limit * 1024 * 1024,
+			max_parallel_steps: configuration.max_parallel_steps,
+			public_url: configuration.public_url.clone(),
+			idempotency_retention: configuration.idempotency_retention,
+		}
+	}
+}
 
-		let connection_manager = ConnectionManager::<ReadConnection>::new(&format!(
-			"{}/littleci.sqlite3",
-			config.data_dir
-		));
-		let pool = Pool::builder()
-			.max_size(5) // TODO Make configurable probs?
-			.build(connection_manager)
-			.expect("Unable to create connection pool");
-
-		let write_connection =
-			WriteConnection::establish(&format!("{}/littleci.sqlite3", config.data_dir,))
-				.expect("Unable to create write connection");
-
-		let connection_manager = DbConnectionManager {
-			write_connection: Arc::new(Mutex::new(write_connection)),
-			read_pool: Arc::new(Mutex::new(pool)),
+/// Open the database, running any pending migrations.
+fn connect(config: &AppConfig) -> DbConnectionManager {
+	let connection_manager = ConnectionManager::<ReadConnection>::new(&format!(
+		"{}/littleci.sqlite3",
+		config.data_dir
+	));
+	let pool = Pool::builder()
+		.max_size(5) // TODO Make configurable probs?
+		.build(connection_manager)
+		.expect("Unable to create connection pool");
+
+	let write_connection =
+		WriteConnection::establish(&format!("{}/littleci.sqlite3", config.data_dir,))
+			.expect("Unable to create write connection");
+
+	let connection_manager = DbConnectionManager {
+		write_connection: Arc::new(Mutex::new(write_connection)),
+		read_pool: Arc::new(Mutex::new(pool)),
+	};
+
+	{
+		let write_conn = connection_manager.get_write();
+		match embedded_migrations::run_with_output(&*write_conn, &mut std::io::stdout()) {
+			Ok(()) => debug!("Database migrations completed."),
+			Err(error) => error!("Could not run database migrations. {}", error),
 		};
+	}
 
-		{
-			let write_conn = connection_manager.get_write();
-			match embedded_migrations::run_with_output(&*write_conn, &mut std::io::stdout()) {
-				Ok(()) => debug!("Database migrations completed."),
-				Err(error) => error!("Could not run database migrations. {}", error),
-			};
-		}
+	connection_manager
+}
+
+impl From<PersistedConfig> for AppState {
+	fn from(configuration: PersistedConfig) -> Self {
+		let config = AppConfig::from(configuration);
+		let connection_manager = connect(&config);
 
 		let config = Arc::new(config);
 		let queue_manager = QueueManager::new(connection_manager.clone(), config.clone());
@@ -254,6 +272,16 @@ fn main() {
 			(about: "Launch LittleCI's HTTP server")
 			(@arg CONFIG_FILE: --config +takes_value "Path to config file")
 		)
+		(@subcommand hook =>
+			(about: "Manage git hooks which notify LittleCI")
+			(@subcommand install =>
+				(about: "Install a post-receive hook into a bare repository")
+				(@arg CONFIG_FILE: --config +takes_value "Path to config file")
+				(@arg REPOSITORY: --repo +takes_value +required "Slug of the repository to notify")
+				(@arg URL: --url +takes_value "LittleCI's URL. Defaults to the configured public URL")
+				(@arg PATH: +required "Path to the bare repository")
+			)
+		)
 	)
 	.get_matches();
 
@@ -298,6 +326,39 @@ fn main() {
 			Err(error) => eprintln!("Error loading configuration. {}", error),
 		}
 	}
+
+	if let Some(matches) = command_matches
+		.subcommand_matches("hook")
+		.and_then(|matches| matches.subcommand_matches("install"))
+	{
+		let working_dir = current_dir().expect("Working directory is invalid");
+		let working_dir = working_dir.to_str().unwrap_or("./");
+		let config_path = matches.value_of("CONFIG_FILE").unwrap_or(&working_dir);
+
+		if let Err(error) = install_hook(config_path, matches) {
+			eprintln!("Unable to install hook. {}", error);
+			process::exit(1);
+		}
+	}
+}
+
+fn install_hook(config_path: &str, matches: &clap::ArgMatches) -> Result<(), Error> {
+	let config = AppConfig::from(load_app_config(config_path)?);
+	let connection_manager = connect(&config);
+
+	let slug = matches.value_of("REPOSITORY").expect("Repository is required");
+	let repository = Repositories::new(connection_manager)
+		.find_by_slug(slug)
+		.ok_or_else(|| format_err!("Repository `{}` not found", slug))?;
+
+	let default_url = config.public_url();
+	let url = matches.value_of("URL").unwrap_or(&default_url);
+
+	let path = matches.value_of("PATH").expect("Path is required");
+	let hook_path = hook::install(path, url, &repository)?;
+	println!("Installed {} to notify {}", hook_path.display(), url);
+
+	Ok(())
 }
 
 fn create_default_user(app_state: &AppState) {
@@ -0,0 +1,108 @@
+use chrono::NaiveDateTime;
+use diesel::insert_into;
+use diesel::prelude::*;
+use failure::{format_err, Error};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use schema::builds;
+
+use crate::queue::Build;
+use crate::DbConnectionManager;
+
+use super::queues::Queues;
+use super::schema;
+
+#[derive(Identifiable, Queryable, Debug, Clone)]
+#[table_name = "builds"]
+struct BuildRecord {
+	id: String,
+	created_at: NaiveDateTime,
+	repository_id: String,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "builds"]
+struct NewBuildRecord {
+	id: String,
+	created_at: NaiveDateTime,
+	repository_id: String,
+}
+
+#[derive(Debug)]
+pub struct Builds {
+	connection_manager: DbConnectionManager,
+}
+
+impl Builds {
+	pub fn new(connection_manager: DbConnectionManager) -> Self {
+		Self { connection_manager }
+	}
+
+	pub fn create(&self, build: &Build) -> Result<(), Error> {
+		let result = insert_into(builds::table)
+			.values(NewBuildRecord {
+				id: build.id.clone(),
+				created_at: build.created_at,
+				repository_id: build.repository_id.clone(),
+			})
+			.execute(&*self.connection_manager.get_write());
+
+		match result {
+			Err(error) => Err(format_err!("Unable to save build {}. {}", build.id, error)),
+			_ => Ok(()),
+		}
+	}
+
+	pub fn delete_by_id(&self, build_id: &str) -> Result<(), Error> {
+		use schema::builds::dsl::*;
+
+		let result = diesel::delete(builds.filter(id.eq(build_id)))
+			.execute(&*self.connection_manager.get_write());
+
+		match result {
+			Err(error) => Err(format_err!(
+				"Unable to delete build {}. {}",
+				build_id,
+				error
+			)),
+			_ => Ok(()),
+		}
+	}
+
+	/// Find a build and its jobs. The status of the build is derived from the status of the jobs.
+	pub fn find(&self, repository: &str, build_id: &str) -> Result<Build, Error> {
+		use schema::builds::dsl::*;
+
+		let record = builds
+			.filter(id.eq(build_id))
+			.filter(repository_id.eq(repository))
+			.first::<BuildRecord>(&self.connection_manager.get_read());
+
+		match record {
+			Ok(record) => {
+				let jobs = Queues::new(self.connection_manager.clone()).all_for_build(&record.id)?;
+
+				Ok(Build {
+					status: Build::combined_status(&jobs),
+					id: record.id,
+					repository_id: record.repository_id,
+					created_at: record.created_at,
+					jobs,
+				})
+			}
+			Err(error) => {
+				error!(
+					"Unable to fetch build {} for {}. {}",
+					build_id, repository, error
+				);
+				Err(format_err!(
+					"Unable to fetch build {} for {}.",
+					build_id,
+					repository
+				))
+			}
+		}
+	}
+}
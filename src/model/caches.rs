@@ -0,0 +1,142 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::{delete, insert_into, update};
+use failure::{format_err, Error};
+use serde_derive::Serialize;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use schema::caches;
+
+use crate::util::{serialize_date, utc_now};
+use crate::DbConnectionManager;
+
+use super::schema;
+
+#[derive(Serialize, Identifiable, Queryable, Debug, Clone)]
+#[table_name = "caches"]
+pub struct Cache {
+	pub id: String,
+	#[serde(rename = "key")]
+	pub cache_key: String,
+	/// Size in bytes
+	pub size: i64,
+	#[serde(serialize_with = "serialize_date")]
+	pub created_at: NaiveDateTime,
+	#[serde(serialize_with = "serialize_date")]
+	pub last_used_at: NaiveDateTime,
+	pub repository_id: String,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "caches"]
+struct NewCacheRecord {
+	id: String,
+	cache_key: String,
+	size: i64,
+	repository_id: String,
+}
+
+#[derive(Debug)]
+pub struct Caches {
+	connection_manager: DbConnectionManager,
+}
+
+impl Caches {
+	pub fn new(connection_manager: DbConnectionManager) -> Self {
+		Self { connection_manager }
+	}
+
+	pub fn create(&self, repository: &str, key: &str, cache_size: i64) -> Result<Cache, Error> {
+		use schema::caches::dsl::*;
+
+		let cache_id = nanoid::custom(24, &crate::ALPHA_NUMERIC);
+
+		let result = insert_into(caches)
+			.values(NewCacheRecord {
+				id: cache_id.clone(),
+				cache_key: key.to_owned(),
+				size: cache_size,
+				repository_id: repository.to_owned(),
+			})
+			.execute(&*self.connection_manager.get_write());
+
+		match result {
+			Err(error) => Err(format_err!("Unable to save cache {}. {}", key, error)),
+			_ => match caches
+				.filter(id.eq(&cache_id))
+				.first::<Cache>(&self.connection_manager.get_read())
+			{
+				Ok(cache) => Ok(cache),
+				Err(error) => Err(format_err!("Unable to fetch saved cache. {}", error)),
+			},
+		}
+	}
+
+	pub fn find_by_key(&self, repository: &str, key: &str) -> Option<Cache> {
+		use schema::caches::dsl::*;
+
+		caches
+			.filter(repository_id.eq(repository))
+			.filter(cache_key.eq(key))
+			.first::<Cache>(&self.connection_manager.get_read())
+			.ok()
+	}
+
+	pub fn touch(&self, cache: &Cache) -> Result<(), Error> {
+		use schema::caches::dsl::*;
+
+		let result = update(caches.find(&cache.id))
+			.set(last_used_at.eq(utc_now()))
+			.execute(&*self.connection_manager.get_write());
+
+		match result {
+			Err(error) => Err(format_err!("Unable to update cache {}. {}", cache.id, error)),
+			_ => Ok(()),
+		}
+	}
+
+	pub fn all(&self) -> Result<Vec<Cache>, Error> {
+		use schema::caches::dsl::*;
+
+		let records = caches
+			.order(last_used_at.asc())
+			.load::<Cache>(&self.connection_manager.get_read());
+
+		match records {
+			Ok(records) => Ok(records),
+			Err(error) => Err(format_err!("Unable to fetch caches. {}", error)),
+		}
+	}
+
+	pub fn all_for_repository(&self, repository: &str) -> Result<Vec<Cache>, Error> {
+		use schema::caches::dsl::*;
+
+		let records = caches
+			.filter(repository_id.eq(repository))
+			.order(last_used_at.desc())
+			.load::<Cache>(&self.connection_manager.get_read());
+
+		match records {
+			Ok(records) => Ok(records),
+			Err(error) => Err(format_err!(
+				"Unable to fetch caches for {}. {}",
+				repository,
+				error
+			)),
+		}
+	}
+
+	pub fn delete_by_id(&self, cache_id: &str) -> Result<(), Error> {
+		use schema::caches::dsl::*;
+
+		let result = delete(caches.filter(id.eq(cache_id)))
+			.execute(&*self.connection_manager.get_write());
+
+		match result {
+			Err(error) => Err(format_err!("Unable to delete cache {}. {}", cache_id, error)),
+			_ => Ok(()),
+		}
+	}
+}
@@ -12,9 +12,14 @@ use parking_lot::MutexGuard;
 use std::fmt;
 use std::sync::Arc;
 
+pub mod builds;
+pub mod caches;
+pub mod polled_refs;
 pub mod queues;
 pub mod repositories;
+pub mod schedule_runs;
 pub mod schema;
+pub mod test_results;
 pub mod users;
 
 /// Source: https://stackoverflow.com/a/57717533
@@ -0,0 +1,133 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::{delete, insert_into, update};
+use failure::{format_err, Error};
+use std::collections::HashMap;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use schema::polled_refs;
+
+use crate::util::utc_now;
+use crate::DbConnectionManager;
+
+use super::schema;
+
+#[derive(Identifiable, Queryable, Debug, Clone)]
+#[table_name = "polled_refs"]
+struct PolledRefRecord {
+	id: String,
+	url: String,
+	ref_name: String,
+	sha: String,
+	updated_at: NaiveDateTime,
+	repository_id: String,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "polled_refs"]
+struct NewPolledRefRecord {
+	id: String,
+	url: String,
+	ref_name: String,
+	sha: String,
+	updated_at: NaiveDateTime,
+	repository_id: String,
+}
+
+/// The commit each branch of a polled remote last pointed to, so that moves aren't repeated or
+/// lost when LittleCI restarts.
+#[derive(Debug)]
+pub struct PolledRefs {
+	connection_manager: DbConnectionManager,
+}
+
+impl PolledRefs {
+	pub fn new(connection_manager: DbConnectionManager) -> Self {
+		Self { connection_manager }
+	}
+
+	/// Last seen commits of a remote, by branch
+	pub fn for_url(&self, repository: &str, remote: &str) -> HashMap<String, String> {
+		use schema::polled_refs::dsl::*;
+
+		polled_refs
+			.filter(repository_id.eq(repository))
+			.filter(url.eq(remote))
+			.load::<PolledRefRecord>(&self.connection_manager.get_read())
+			.unwrap_or_else(|error| {
+				error!("Unable to load polled refs of `{}`. {}", remote, error);
+				vec![]
+			})
+			.into_iter()
+			.map(|record| (record.ref_name, record.sha))
+			.collect()
+	}
+
+	pub fn record(
+		&self,
+		repository: &str,
+		remote: &str,
+		name: &str,
+		commit: &str,
+	) -> Result<(), Error> {
+		use schema::polled_refs::dsl::*;
+
+		let existing = polled_refs
+			.filter(repository_id.eq(repository))
+			.filter(url.eq(remote))
+			.filter(ref_name.eq(name))
+			.first::<PolledRefRecord>(&self.connection_manager.get_read())
+			.ok();
+
+		let result = match existing {
+			Some(record) => update(polled_refs.find(record.id))
+				.set((sha.eq(commit), updated_at.eq(utc_now())))
+				.execute(&*self.connection_manager.get_write()),
+			None => insert_into(polled_refs)
+				.values(NewPolledRefRecord {
+					id: nanoid::custom(24, &crate::ALPHA_NUMERIC),
+					url: remote.to_owned(),
+					ref_name: name.to_owned(),
+					sha: commit.to_owned(),
+					updated_at: utc_now(),
+					repository_id: repository.to_owned(),
+				})
+				.execute(&*self.connection_manager.get_write()),
+		};
+
+		match result {
+			Err(error) => Err(format_err!(
+				"Unable to record `{}` of `{}`. {}",
+				name,
+				remote,
+				error
+			)),
+			_ => Ok(()),
+		}
+	}
+
+	/// Forget a branch which no longer exists, so that it's treated as new if it's pushed again
+	pub fn remove(&self, repository: &str, remote: &str, name: &str) -> Result<(), Error> {
+		use schema::polled_refs::dsl::*;
+
+		let result = delete(
+			polled_refs
+				.filter(repository_id.eq(repository))
+				.filter(url.eq(remote))
+				.filter(ref_name.eq(name)),
+		)
+		.execute(&*self.connection_manager.get_write());
+
+		match result {
+			Err(error) => Err(format_err!(
+				"Unable to remove `{}` of `{}`. {}",
+				name,
+				remote,
+				error
+			)),
+			_ => Ok(()),
+		}
+	}
+}
@@ -4,6 +4,7 @@ use diesel::{insert_into, update};
 use failure::{format_err, Error};
 use serde_derive::Serialize;
 use serde_json;
+use std::collections::HashMap;
 
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
@@ -11,6 +12,7 @@ use log::{debug, error, info, warn};
 use schema::{queue, queue_logs};
 
 use crate::model::repositories::{Repository, RepositoryRecord};
+use crate::queue::steps::StepState;
 use crate::queue::{ExecutionStatus, QueueItem, QueueLogItem};
 use crate::util::serialize_date;
 use crate::DbConnectionManager;
@@ -57,6 +59,12 @@ struct QueueRecord {
 	created_at: NaiveDateTime,
 	updated_at: NaiveDateTime,
 	repository_id: String,
+	outputs: Option<String>,
+	build_id: Option<String>,
+	matrix: Option<String>,
+	upstream_job_id: Option<String>,
+	steps: Option<String>,
+	idempotency_key: Option<String>,
 }
 
 #[derive(Identifiable, Queryable, Associations, AsChangeset, PartialEq, Debug, Clone)]
@@ -68,6 +76,7 @@ struct QueueLogRecord {
 	reason: Option<String>,
 	created_at: NaiveDateTime,
 	queue_id: String,
+	username: Option<String>,
 }
 
 impl From<(&str, &Option<String>)> for ExecutionStatus {
@@ -75,6 +84,7 @@ impl From<(&str, &Option<String>)> for ExecutionStatus {
 		match status {
 			("cancelled", None) => ExecutionStatus::Cancelled,
 			("queued", None) => ExecutionStatus::Queued,
+			("awaiting_approval", None) => ExecutionStatus::AwaitingApproval,
 			("running", None) => ExecutionStatus::Running,
 			("failed", Some(reason)) => ExecutionStatus::Failed(reason.parse().unwrap_or(-1)),
 			("completed", None) => ExecutionStatus::Completed,
@@ -89,6 +99,7 @@ impl Into<(String, Option<String>)> for ExecutionStatus {
 		match self {
 			ExecutionStatus::Cancelled => ("cancelled".into(), None),
 			ExecutionStatus::Queued => ("queued".into(), None),
+			ExecutionStatus::AwaitingApproval => ("awaiting_approval".into(), None),
 			ExecutionStatus::Running => ("running".into(), None),
 			ExecutionStatus::Failed(exit_code) => ("failed".into(), Some(format!("{}", exit_code))),
 			ExecutionStatus::Completed => ("completed".into(), None),
@@ -101,11 +112,42 @@ impl Into<(String, Option<String>)> for ExecutionStatus {
 impl From<(QueueRecord, Vec<QueueLogRecord>)> for QueueItem {
 	fn from(record: (QueueRecord, Vec<QueueLogRecord>)) -> QueueItem {
 		let (record, logs) = record;
+
+		let outputs: HashMap<String, String> = match &record.outputs {
+			Some(outputs) => serde_json::from_str(&outputs).unwrap_or_else(|_| {
+				error!("Unable to parse outputs JSON for job {}", record.id);
+				HashMap::default()
+			}),
+			None => HashMap::default(),
+		};
+
+		let matrix: HashMap<String, String> = match &record.matrix {
+			Some(matrix) => serde_json::from_str(&matrix).unwrap_or_else(|_| {
+				error!("Unable to parse matrix JSON for job {}", record.id);
+				HashMap::default()
+			}),
+			None => HashMap::default(),
+		};
+
+		let steps: Vec<StepState> = match &record.steps {
+			Some(steps) => serde_json::from_str(&steps).unwrap_or_else(|_| {
+				error!("Unable to parse steps JSON for job {}", record.id);
+				Vec::default()
+			}),
+			None => Vec::default(),
+		};
+
 		QueueItem {
 			id: record.id,
 			repository_id: record.repository_id,
 			status: ExecutionStatus::from((&*record.status, &record.reason)),
 			data: serde_json::from_str(&record.data).unwrap(),
+			outputs,
+			build_id: record.build_id,
+			matrix,
+			upstream_job_id: record.upstream_job_id,
+			steps,
+			idempotency_key: record.idempotency_key,
 			created_at: record.created_at,
 			updated_at: record.updated_at,
 			logs: logs.into_iter().map(QueueLogItem::from).collect(),
@@ -117,6 +159,7 @@ impl From<QueueLogRecord> for QueueLogItem {
 	fn from(record: QueueLogRecord) -> QueueLogItem {
 		QueueLogItem {
 			status: ExecutionStatus::from((&*record.status, &record.reason)),
+			username: record.username,
 			created_at: record.created_at,
 		}
 	}
@@ -132,6 +175,12 @@ struct NewQueueRecord {
 	created_at: NaiveDateTime,
 	updated_at: NaiveDateTime,
 	repository_id: String,
+	outputs: Option<String>,
+	build_id: Option<String>,
+	matrix: Option<String>,
+	upstream_job_id: Option<String>,
+	steps: Option<String>,
+	idempotency_key: Option<String>,
 }
 
 impl From<&QueueItem> for NewQueueRecord {
@@ -146,6 +195,18 @@ impl From<&QueueItem> for NewQueueRecord {
 			created_at: item.created_at,
 			updated_at: item.updated_at,
 			repository_id: item.repository_id.clone(),
+			outputs: Some(
+				serde_json::to_string(&item.outputs).expect("Unable to serialize outputs to JSON"),
+			),
+			build_id: item.build_id.clone(),
+			matrix: Some(
+				serde_json::to_string(&item.matrix).expect("Unable to serialize matrix to JSON"),
+			),
+			upstream_job_id: item.upstream_job_id.clone(),
+			steps: Some(
+				serde_json::to_string(&item.steps).expect("Unable to serialize steps to JSON"),
+			),
+			idempotency_key: item.idempotency_key.clone(),
 		}
 	}
 }
@@ -157,6 +218,7 @@ struct NewQueueLogRecord {
 	reason: Option<String>,
 	created_at: NaiveDateTime,
 	queue_id: String,
+	username: Option<String>,
 }
 
 #[derive(Debug)]
@@ -182,13 +244,38 @@ impl Queues {
 		match result {
 			Err(error) => error!("Unable to persist queue item. {}", error),
 			_ => {
-				if let Err(error) = self.add_queue_log_item(&item) {
+				if let Err(error) = self.add_queue_log_item(&item, None) {
 					error!("Unable to update queue log for {}. {}", item.id, error);
 				}
 			}
 		};
 	}
 
+	/// Add a job with an idempotency key, unless a job holding the same key was added first, in
+	/// which case that job is returned instead.
+	pub fn push_idempotent(&self, item: &QueueItem) -> Result<Option<QueueItem>, Error> {
+		use diesel::result::{DatabaseErrorKind, Error as DieselError};
+		use schema::queue::dsl::*;
+
+		let result = insert_into(queue)
+			.values(NewQueueRecord::from(item))
+			.execute(&*self.connection_manager.get_write());
+
+		match result {
+			Ok(_) => {
+				self.add_queue_log_item(&item, None)?;
+				Ok(None)
+			}
+			Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => queue
+				.filter(repository_id.eq(&item.repository_id))
+				.filter(idempotency_key.eq(&item.idempotency_key))
+				.first::<QueueRecord>(&self.connection_manager.get_read())
+				.map(|record| Some(QueueItem::from((record, Vec::new()))))
+				.map_err(|error| format_err!("Unable to fetch job by idempotency key. {}", error)),
+			Err(error) => Err(format_err!("Unable to persist queue item. {}", error)),
+		}
+	}
+
 	pub fn next_queued(&self, record_id: &str) -> Option<QueueItem> {
 		use schema::queue::dsl::*;
 
@@ -206,6 +293,11 @@ impl Queues {
 	}
 
 	pub fn update_status(&self, item: &QueueItem) -> Result<(), Error> {
+		self.update_status_by(item, None)
+	}
+
+	/// Update the status of a job, recording the user who changed it in the job's logs.
+	pub fn update_status_by(&self, item: &QueueItem, user: Option<&str>) -> Result<(), Error> {
 		use schema::queue::dsl::*;
 
 		let (new_status, new_reason) = item.status.clone().into();
@@ -224,7 +316,7 @@ impl Queues {
 				item.id,
 				error
 			)),
-			_ => match self.add_queue_log_item(&item) {
+			_ => match self.add_queue_log_item(&item, user) {
 				Err(error) => Err(format_err!(
 					"Unable to update status for {}. {}",
 					item.id,
@@ -235,7 +327,74 @@ impl Queues {
 		}
 	}
 
-	fn add_queue_log_item(&self, item: &QueueItem) -> Result<(), Error> {
+	/// All jobs which are waiting to be approved, with their logs.
+	pub fn awaiting_approval(&self) -> Result<Vec<QueueItem>, Error> {
+		use schema::queue::dsl::*;
+
+		let (awaiting_status, _): (String, Option<String>) =
+			ExecutionStatus::AwaitingApproval.into();
+		let records = queue
+			.filter(status.eq(awaiting_status))
+			.load::<QueueRecord>(&self.connection_manager.get_read())
+			.map_err(|error| format_err!("Unable to fetch jobs awaiting approval. {}", error))?;
+
+		let logs = QueueLogRecord::belonging_to(&records)
+			.load::<QueueLogRecord>(&self.connection_manager.get_read())
+			.map_err(|error| format_err!("Unable to load job logs. {}", error))?
+			.grouped_by(&records);
+
+		Ok(records
+			.into_iter()
+			.zip(logs)
+			.map(|(record, logs)| QueueItem::from((record, logs)))
+			.collect())
+	}
+
+	pub fn update_outputs(&self, item: &QueueItem) -> Result<(), Error> {
+		use schema::queue::dsl::*;
+
+		let new_outputs = serde_json::to_string(&item.outputs)?;
+
+		let result = update(queue.find(&item.id))
+			.set((
+				outputs.eq(Some(new_outputs)),
+				updated_at.eq(Utc::now().naive_utc()),
+			))
+			.execute(&*self.connection_manager.get_write());
+
+		match result {
+			Err(error) => Err(format_err!(
+				"Unable to update outputs for {}. {}",
+				item.id,
+				error
+			)),
+			_ => Ok(()),
+		}
+	}
+
+	pub fn update_steps(&self, item: &QueueItem) -> Result<(), Error> {
+		use schema::queue::dsl::*;
+
+		let new_steps = serde_json::to_string(&item.steps)?;
+
+		let result = update(queue.find(&item.id))
+			.set((
+				steps.eq(Some(new_steps)),
+				updated_at.eq(Utc::now().naive_utc()),
+			))
+			.execute(&*self.connection_manager.get_write());
+
+		match result {
+			Err(error) => Err(format_err!(
+				"Unable to update steps for {}. {}",
+				item.id,
+				error
+			)),
+			_ => Ok(()),
+		}
+	}
+
+	fn add_queue_log_item(&self, item: &QueueItem, user: Option<&str>) -> Result<(), Error> {
 		use schema::queue_logs::dsl::*;
 
 		let (new_status, new_reason) = item.status.clone().into();
@@ -246,6 +405,7 @@ impl Queues {
 				reason: new_reason,
 				created_at: Utc::now().naive_utc(),
 				queue_id: item.id.clone(),
+				username: user.map(String::from),
 			})
 			.execute(&*self.connection_manager.get_write());
 
@@ -304,6 +464,104 @@ impl Queues {
 		}
 	}
 
+	pub fn all_for_build(&self, build: &str) -> Result<Vec<QueueItem>, Error> {
+		use schema::queue::dsl::*;
+
+		let records = queue
+			.filter(build_id.eq(build))
+			.order(created_at.asc())
+			.load::<QueueRecord>(&self.connection_manager.get_read());
+
+		match records {
+			Ok(records) => Ok(records
+				.into_iter()
+				.map(|record| QueueItem::from((record, Vec::new())))
+				.collect()),
+			Err(error) => {
+				error!("Unable to fetch jobs for build {}. {}", build, error);
+				Err(format_err!("Unable to fetch jobs for build {}.", build))
+			}
+		}
+	}
+
+	/// Finished jobs for the same repository and branch which were created before `item`, most
+	/// recent first.
+	pub fn previous_on_branch(&self, item: &QueueItem, limit: usize) -> Result<Vec<QueueItem>, Error> {
+		use schema::queue::dsl::*;
+
+		let (completed_status, _): (String, Option<String>) = ExecutionStatus::Completed.into();
+		let (failed_status, _): (String, Option<String>) = ExecutionStatus::Failed(0).into();
+		let branch = item.data.inner().get("LITTLECI_GIT_BRANCH");
+
+		let records = queue
+			.filter(repository_id.eq(&item.repository_id))
+			.filter(created_at.lt(item.created_at))
+			.filter(status.eq_any(vec![completed_status, failed_status]))
+			.order(created_at.desc())
+			.limit(200)
+			.load::<QueueRecord>(&self.connection_manager.get_read())?;
+
+		Ok(records
+			.into_iter()
+			.map(|record| QueueItem::from((record, Vec::new())))
+			.filter(|job| job.data.inner().get("LITTLECI_GIT_BRANCH") == branch)
+			// Only compare matrix jobs with the same combination
+			.filter(|job| job.matrix == item.matrix)
+			.take(limit)
+			.collect())
+	}
+
+	pub fn find_by_id(&self, job_id: &str) -> Option<QueueItem> {
+		use schema::queue::dsl::*;
+
+		queue
+			.filter(id.eq(job_id))
+			.first::<QueueRecord>(&self.connection_manager.get_read())
+			.ok()
+			.map(|record| QueueItem::from((record, Vec::new())))
+	}
+
+	/// The job queued with an idempotency key since `since`, if any.
+	pub fn find_by_idempotency_key(
+		&self,
+		repository: &str,
+		key: &str,
+		since: NaiveDateTime,
+	) -> Option<QueueItem> {
+		use schema::queue::dsl::*;
+
+		queue
+			.filter(repository_id.eq(repository))
+			.filter(idempotency_key.eq(key))
+			.filter(created_at.ge(since))
+			.order(created_at.desc())
+			.first::<QueueRecord>(&self.connection_manager.get_read())
+			.ok()
+			.map(|record| QueueItem::from((record, Vec::new())))
+	}
+
+	/// Free an idempotency key held by jobs queued before `before`. Keys are unique within a
+	/// repository, so expired keys must be released before they can queue a job again.
+	pub fn release_idempotency_key(
+		&self,
+		repository: &str,
+		key: &str,
+		before: NaiveDateTime,
+	) -> Result<(), Error> {
+		use schema::queue::dsl::*;
+
+		update(
+			queue
+				.filter(repository_id.eq(repository))
+				.filter(idempotency_key.eq(key))
+				.filter(created_at.lt(before)),
+		)
+		.set(idempotency_key.eq(None::<String>))
+		.execute(&*self.connection_manager.get_write())
+		.map(|_| ())
+		.map_err(|error| format_err!("Unable to release idempotency key `{}`. {}", key, error))
+	}
+
 	pub fn job(&self, repository: &str, job_id: &str) -> Result<QueueItem, Error> {
 		use schema::queue::dsl::*;
 
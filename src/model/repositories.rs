@@ -1,15 +1,25 @@
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
+use failure::{format_err, Error};
 use serde_derive::{Deserialize, Serialize};
 use serde_json;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
 
 use schema::repositories;
 
-use crate::config::Trigger;
+use crate::config::{
+	CacheEntry, InboundWebhook, Matrix, SkipRule, StatusReporting, Step, Trigger, Upstream,
+};
+use crate::queue::inbound::validate as validate_inbound;
+use crate::queue::poller::validate as validate_polls;
+use crate::queue::preprocess::validate as validate_skip_rules;
+use crate::queue::scheduler::validate as validate_schedules;
+use crate::queue::status::validate as validate_status_reporting;
+use crate::queue::triggers::validate as validate_triggers;
+use crate::queue::steps::validate as validate_steps;
 use crate::util::{serialize_date, utc_now};
 use crate::DbConnectionManager;
 use crate::{kebab_case, HashedValue};
@@ -35,6 +45,48 @@ pub struct Repository {
 	pub triggers: Vec<Trigger>,
 	#[serde(default)]
 	pub webhooks: Vec<String>,
+	/// Paths or glob patterns of JUnit XML reports written by the command
+	#[serde(default)]
+	pub test_reports: Vec<String>,
+	/// Dependency caches restored before and saved after each build
+	#[serde(default)]
+	pub caches: Vec<CacheEntry>,
+	/// Run one job per combination of matrix values
+	#[serde(default)]
+	pub matrix: Option<Matrix>,
+	/// Repositories whose jobs trigger a job for this repository when they finish
+	#[serde(default)]
+	pub upstream: Vec<Upstream>,
+	/// Named steps which run as a dependency graph instead of `run`
+	#[serde(default)]
+	pub steps: Vec<Step>,
+	/// Command to execute after the job completes
+	#[serde(default)]
+	pub after_success: Option<String>,
+	/// Command to execute after the job fails or is cancelled
+	#[serde(default)]
+	pub after_failure: Option<String>,
+	/// Command to execute after every job
+	#[serde(default)]
+	pub always: Option<String>,
+	/// Jobs wait for a user to approve them before they're run
+	#[serde(default)]
+	pub requires_approval: bool,
+	/// Reject jobs which haven't been approved within this many seconds
+	#[serde(default)]
+	pub approval_timeout: Option<i32>,
+	/// Skip jobs whose data matches any of these rules
+	#[serde(default)]
+	pub skip_rules: Vec<SkipRule>,
+	/// Maps the payload of the generic webhook to job variables
+	#[serde(default)]
+	pub inbound: Option<InboundWebhook>,
+	/// Report job statuses to the commit status API of a git forge
+	#[serde(default)]
+	pub status_reporting: Option<StatusReporting>,
+	/// Variable in a notification's data identifying repeated deliveries of the notification
+	#[serde(default)]
+	pub idempotency_variable: Option<String>,
 	#[serde(skip)]
 	pub deleted: bool,
 	#[serde(
@@ -74,6 +126,70 @@ impl From<RepositoryRecord> for Repository {
 			None => Vec::default(),
 		};
 
+		let test_reports: Vec<String> = match &record.test_reports {
+			Some(test_reports) => serde_json::from_str(&test_reports).unwrap_or_else(|_| {
+				error!("Unable to parse test report JSON for repository {}", record.id);
+				Vec::default()
+			}),
+			None => Vec::default(),
+		};
+
+		let caches: Vec<CacheEntry> = match &record.caches {
+			Some(caches) => serde_json::from_str(&caches).unwrap_or_else(|_| {
+				error!("Unable to parse cache JSON for repository {}", record.id);
+				Vec::default()
+			}),
+			None => Vec::default(),
+		};
+
+		let matrix: Option<Matrix> = match &record.matrix {
+			Some(matrix) => serde_json::from_str(&matrix).unwrap_or_else(|_| {
+				error!("Unable to parse matrix JSON for repository {}", record.id);
+				None
+			}),
+			None => None,
+		};
+
+		let upstream: Vec<Upstream> = match &record.upstream {
+			Some(upstream) => serde_json::from_str(&upstream).unwrap_or_else(|_| {
+				error!("Unable to parse upstream JSON for repository {}", record.id);
+				Vec::default()
+			}),
+			None => Vec::default(),
+		};
+
+		let steps: Vec<Step> = match &record.steps {
+			Some(steps) => serde_json::from_str(&steps).unwrap_or_else(|_| {
+				error!("Unable to parse steps JSON for repository {}", record.id);
+				Vec::default()
+			}),
+			None => Vec::default(),
+		};
+
+		let skip_rules: Vec<SkipRule> = match &record.skip_rules {
+			Some(skip_rules) => serde_json::from_str(&skip_rules).unwrap_or_else(|_| {
+				error!("Unable to parse skip rule JSON for repository {}", record.id);
+				Vec::default()
+			}),
+			None => Vec::default(),
+		};
+
+		let inbound: Option<InboundWebhook> = match &record.inbound {
+			Some(inbound) => serde_json::from_str(&inbound).unwrap_or_else(|_| {
+				error!("Unable to parse inbound webhook JSON for repository {}", record.id);
+				None
+			}),
+			None => None,
+		};
+
+		let status_reporting: Option<StatusReporting> = match &record.status_reporting {
+			Some(status_reporting) => serde_json::from_str(&status_reporting).unwrap_or_else(|_| {
+				error!("Unable to parse status reporting JSON for repository {}", record.id);
+				None
+			}),
+			None => None,
+		};
+
 		Self {
 			id: record.id,
 			slug: record.slug,
@@ -84,6 +200,20 @@ impl From<RepositoryRecord> for Repository {
 			variables,
 			triggers,
 			webhooks,
+			test_reports,
+			caches,
+			matrix,
+			upstream,
+			steps,
+			after_success: record.after_success,
+			after_failure: record.after_failure,
+			always: record.always,
+			requires_approval: record.requires_approval != 0,
+			approval_timeout: record.approval_timeout,
+			skip_rules,
+			inbound,
+			status_reporting,
+			idempotency_variable: record.idempotency_variable,
 			deleted: record.deleted != 0,
 			created_at: record.created_at,
 			updated_at: record.updated_at,
@@ -110,6 +240,20 @@ pub struct RepositoryRecord {
 	pub deleted: i32,
 	pub created_at: NaiveDateTime,
 	pub updated_at: NaiveDateTime,
+	pub test_reports: Option<String>,
+	pub caches: Option<String>,
+	pub matrix: Option<String>,
+	pub upstream: Option<String>,
+	pub steps: Option<String>,
+	pub after_success: Option<String>,
+	pub after_failure: Option<String>,
+	pub always: Option<String>,
+	pub requires_approval: i32,
+	pub approval_timeout: Option<i32>,
+	pub skip_rules: Option<String>,
+	pub inbound: Option<String>,
+	pub status_reporting: Option<String>,
+	pub idempotency_variable: Option<String>,
 }
 
 impl From<Repository> for RepositoryRecord {
@@ -136,6 +280,44 @@ impl From<Repository> for RepositoryRecord {
 			deleted: record.deleted as i32,
 			created_at: record.created_at,
 			updated_at: record.updated_at,
+			test_reports: Some(
+				serde_json::to_string(&record.test_reports)
+					.expect("Unable to serialize test reports to JSON".into()),
+			),
+			caches: Some(
+				serde_json::to_string(&record.caches)
+					.expect("Unable to serialize caches to JSON".into()),
+			),
+			matrix: Some(
+				serde_json::to_string(&record.matrix)
+					.expect("Unable to serialize matrix to JSON".into()),
+			),
+			upstream: Some(
+				serde_json::to_string(&record.upstream)
+					.expect("Unable to serialize upstream to JSON".into()),
+			),
+			steps: Some(
+				serde_json::to_string(&record.steps)
+					.expect("Unable to serialize steps to JSON".into()),
+			),
+			after_success: record.after_success,
+			after_failure: record.after_failure,
+			always: record.always,
+			requires_approval: record.requires_approval as i32,
+			approval_timeout: record.approval_timeout,
+			skip_rules: Some(
+				serde_json::to_string(&record.skip_rules)
+					.expect("Unable to serialize skip rules to JSON".into()),
+			),
+			inbound: Some(
+				serde_json::to_string(&record.inbound)
+					.expect("Unable to serialize inbound webhook to JSON".into()),
+			),
+			status_reporting: Some(
+				serde_json::to_string(&record.status_reporting)
+					.expect("Unable to serialize status reporting to JSON".into()),
+			),
+			idempotency_variable: record.idempotency_variable,
 		}
 	}
 }
@@ -149,6 +331,20 @@ pub struct NewRepositoryRecord {
 	pub variables: Option<String>,
 	pub triggers: Option<String>,
 	pub webhooks: Option<String>,
+	pub test_reports: Option<String>,
+	pub caches: Option<String>,
+	pub matrix: Option<String>,
+	pub upstream: Option<String>,
+	pub steps: Option<String>,
+	pub after_success: Option<String>,
+	pub after_failure: Option<String>,
+	pub always: Option<String>,
+	pub requires_approval: i32,
+	pub approval_timeout: Option<i32>,
+	pub skip_rules: Option<String>,
+	pub inbound: Option<String>,
+	pub status_reporting: Option<String>,
+	pub idempotency_variable: Option<String>,
 }
 
 impl From<Repository> for NewRepositoryRecord {
@@ -169,6 +365,44 @@ impl From<Repository> for NewRepositoryRecord {
 				serde_json::to_string(&record.webhooks)
 					.expect("Unable to serialize webhooks to JSON".into()),
 			),
+			test_reports: Some(
+				serde_json::to_string(&record.test_reports)
+					.expect("Unable to serialize test reports to JSON".into()),
+			),
+			caches: Some(
+				serde_json::to_string(&record.caches)
+					.expect("Unable to serialize caches to JSON".into()),
+			),
+			matrix: Some(
+				serde_json::to_string(&record.matrix)
+					.expect("Unable to serialize matrix to JSON".into()),
+			),
+			upstream: Some(
+				serde_json::to_string(&record.upstream)
+					.expect("Unable to serialize upstream to JSON".into()),
+			),
+			steps: Some(
+				serde_json::to_string(&record.steps)
+					.expect("Unable to serialize steps to JSON".into()),
+			),
+			after_success: record.after_success,
+			after_failure: record.after_failure,
+			always: record.always,
+			requires_approval: record.requires_approval as i32,
+			approval_timeout: record.approval_timeout,
+			skip_rules: Some(
+				serde_json::to_string(&record.skip_rules)
+					.expect("Unable to serialize skip rules to JSON".into()),
+			),
+			inbound: Some(
+				serde_json::to_string(&record.inbound)
+					.expect("Unable to serialize inbound webhook to JSON".into()),
+			),
+			status_reporting: Some(
+				serde_json::to_string(&record.status_reporting)
+					.expect("Unable to serialize status reporting to JSON".into()),
+			),
+			idempotency_variable: record.idempotency_variable,
 		}
 	}
 }
@@ -185,6 +419,41 @@ impl RepositorySecret {
 	}
 }
 
+/// Check the settings of a repository before it's created or saved.
+fn validate(repository: &Repository) -> Result<(), Error> {
+	validate_steps(&repository.steps).map_err(|error| format_err!("Invalid steps. {}", error))?;
+
+	validate_skip_rules(&repository.skip_rules)
+		.map_err(|error| format_err!("Invalid skip rules. {}", error))?;
+
+	validate_schedules(&repository.triggers)
+		.map_err(|error| format_err!("Invalid schedule. {}", error))?;
+
+	validate_triggers(&repository.triggers)
+		.map_err(|error| format_err!("Invalid triggers. {}", error))?;
+
+	validate_polls(&repository.triggers)
+		.map_err(|error| format_err!("Invalid poll trigger. {}", error))?;
+
+	if let Some(matrix) = &repository.matrix {
+		matrix
+			.validate()
+			.map_err(|error| format_err!("Invalid matrix. {}", error))?;
+	}
+
+	if let Some(webhook) = &repository.inbound {
+		validate_inbound(webhook)
+			.map_err(|error| format_err!("Invalid inbound webhook. {}", error))?;
+	}
+
+	if let Some(settings) = &repository.status_reporting {
+		validate_status_reporting(settings)
+			.map_err(|error| format_err!("Invalid status reporting. {}", error))?;
+	}
+
+	Ok(())
+}
+
 pub struct Repositories {
 	connection_manager: DbConnectionManager,
 }
@@ -202,6 +471,14 @@ impl Repositories {
 			return Err(format!("Repository slug already exists"));
 		}
 
+		if let Err(error) = validate(&repository) {
+			return Err(format!("{}", error));
+		}
+
+		if let Err(error) = self.validate_upstream(&repository_slug, &repository.upstream) {
+			return Err(format!("Invalid upstream. {}", error));
+		}
+
 		let repository = NewRepositoryRecord::from(repository);
 
 		let repository_id = nanoid::custom(24, &crate::ALPHA_NUMERIC);
@@ -230,7 +507,7 @@ impl Repositories {
 		}
 	}
 
-	pub fn save(&self, repository: Repository) -> Result<Repository, String> {
+	pub fn save(&self, mut repository: Repository) -> Result<Repository, String> {
 		use schema::repositories::dsl::*;
 
 		let repository_slug = kebab_case(&repository.name);
@@ -240,6 +517,27 @@ impl Repositories {
 			}
 		}
 
+		// Responses leave out the status reporting token, so keep it unless a new one is sent
+		if let Some(settings) = repository.status_reporting.as_mut() {
+			if settings.token.is_empty() {
+				let stored_token = self
+					.find_by_id(&repository.id)
+					.and_then(|stored| stored.status_reporting)
+					.map(|stored| stored.token);
+				if let Some(stored_token) = stored_token {
+					settings.token = stored_token;
+				}
+			}
+		}
+
+		if let Err(error) = validate(&repository) {
+			return Err(format!("{}", error));
+		}
+
+		if let Err(error) = self.validate_upstream(&repository_slug, &repository.upstream) {
+			return Err(format!("Invalid upstream. {}", error));
+		}
+
 		let mut repository = RepositoryRecord::from(repository);
 
 		repository.slug = kebab_case(&repository.name);
@@ -266,6 +564,46 @@ impl Repositories {
 		}
 	}
 
+	/// Check that upstream repositories exist and that none of them is triggered by this
+	/// repository, directly or through other repositories, which would queue jobs in a loop.
+	fn validate_upstream(&self, repository_slug: &str, upstream: &[Upstream]) -> Result<(), Error> {
+		for entry in upstream.iter() {
+			if entry.repository == repository_slug {
+				return Err(format_err!("A repository can't be its own upstream"));
+			}
+			match self.find_by_slug(&entry.repository) {
+				Some(found) if !found.deleted => {}
+				_ => return Err(format_err!("Repository `{}` not found", &entry.repository)),
+			}
+		}
+
+		let mut visited = HashSet::new();
+		let mut pending: Vec<(String, &str)> = upstream
+			.iter()
+			.map(|entry| (entry.repository.clone(), entry.repository.as_str()))
+			.collect();
+		while let Some((current, via)) = pending.pop() {
+			if !visited.insert(current.clone()) {
+				continue;
+			}
+			let found = match self.find_by_slug(&current) {
+				Some(found) => found,
+				None => continue,
+			};
+			for entry in found.upstream.iter() {
+				if entry.repository == repository_slug {
+					return Err(format_err!(
+						"`{}` is triggered by this repository, which would queue jobs in a loop",
+						via
+					));
+				}
+				pending.push((entry.repository.clone(), via));
+			}
+		}
+
+		Ok(())
+	}
+
 	pub fn all(&self) -> Vec<Repository> {
 		use schema::repositories::dsl::*;
 
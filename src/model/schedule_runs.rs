@@ -0,0 +1,86 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::{insert_into, update};
+use failure::{format_err, Error};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use schema::schedule_runs;
+
+use crate::DbConnectionManager;
+
+use super::schema;
+
+#[derive(Identifiable, Queryable, Debug, Clone)]
+#[table_name = "schedule_runs"]
+struct ScheduleRunRecord {
+	id: String,
+	schedule_key: String,
+	last_run_at: NaiveDateTime,
+	repository_id: String,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "schedule_runs"]
+struct NewScheduleRunRecord {
+	id: String,
+	schedule_key: String,
+	last_run_at: NaiveDateTime,
+	repository_id: String,
+}
+
+/// When each of a repository's schedules last ran, so that runs aren't repeated or lost when
+/// LittleCI restarts.
+#[derive(Debug)]
+pub struct ScheduleRuns {
+	connection_manager: DbConnectionManager,
+}
+
+impl ScheduleRuns {
+	pub fn new(connection_manager: DbConnectionManager) -> Self {
+		Self { connection_manager }
+	}
+
+	pub fn last_run(&self, repository: &str, key: &str) -> Option<NaiveDateTime> {
+		use schema::schedule_runs::dsl::*;
+
+		schedule_runs
+			.filter(repository_id.eq(repository))
+			.filter(schedule_key.eq(key))
+			.first::<ScheduleRunRecord>(&self.connection_manager.get_read())
+			.ok()
+			.map(|record| record.last_run_at)
+	}
+
+	pub fn record(&self, repository: &str, key: &str, run_at: NaiveDateTime) -> Result<(), Error> {
+		use schema::schedule_runs::dsl::*;
+
+		let result = match self.last_run(repository, key) {
+			Some(_) => update(
+				schedule_runs
+					.filter(repository_id.eq(repository))
+					.filter(schedule_key.eq(key)),
+			)
+			.set(last_run_at.eq(run_at))
+			.execute(&*self.connection_manager.get_write()),
+			None => insert_into(schedule_runs)
+				.values(NewScheduleRunRecord {
+					id: nanoid::custom(24, &crate::ALPHA_NUMERIC),
+					schedule_key: key.to_owned(),
+					last_run_at: run_at,
+					repository_id: repository.to_owned(),
+				})
+				.execute(&*self.connection_manager.get_write()),
+		};
+
+		match result {
+			Err(error) => Err(format_err!(
+				"Unable to record run of schedule `{}`. {}",
+				key,
+				error
+			)),
+			_ => Ok(()),
+		}
+	}
+}
@@ -1,3 +1,33 @@
+table! {
+    builds (id) {
+        id -> Text,
+        created_at -> Timestamp,
+        repository_id -> Text,
+    }
+}
+
+table! {
+    caches (id) {
+        id -> Text,
+        cache_key -> Text,
+        size -> BigInt,
+        created_at -> Timestamp,
+        last_used_at -> Timestamp,
+        repository_id -> Text,
+    }
+}
+
+table! {
+    polled_refs (id) {
+        id -> Text,
+        url -> Text,
+        ref_name -> Text,
+        sha -> Text,
+        updated_at -> Timestamp,
+        repository_id -> Text,
+    }
+}
+
 table! {
     queue (id) {
         id -> Text,
@@ -7,6 +37,12 @@ table! {
         created_at -> Timestamp,
         updated_at -> Timestamp,
         repository_id -> Text,
+        outputs -> Nullable<Text>,
+        build_id -> Nullable<Text>,
+        matrix -> Nullable<Text>,
+        upstream_job_id -> Nullable<Text>,
+        steps -> Nullable<Text>,
+        idempotency_key -> Nullable<Text>,
     }
 }
 
@@ -17,6 +53,7 @@ table! {
         reason -> Nullable<Text>,
         created_at -> Timestamp,
         queue_id -> Text,
+        username -> Nullable<Text>,
     }
 }
 
@@ -34,6 +71,56 @@ table! {
         deleted -> Integer,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        test_reports -> Nullable<Text>,
+        caches -> Nullable<Text>,
+        matrix -> Nullable<Text>,
+        upstream -> Nullable<Text>,
+        steps -> Nullable<Text>,
+        after_success -> Nullable<Text>,
+        after_failure -> Nullable<Text>,
+        always -> Nullable<Text>,
+        requires_approval -> Integer,
+        approval_timeout -> Nullable<Integer>,
+        skip_rules -> Nullable<Text>,
+        inbound -> Nullable<Text>,
+        status_reporting -> Nullable<Text>,
+        idempotency_variable -> Nullable<Text>,
+    }
+}
+
+table! {
+    schedule_runs (id) {
+        id -> Text,
+        schedule_key -> Text,
+        last_run_at -> Timestamp,
+        repository_id -> Text,
+    }
+}
+
+table! {
+    test_cases (id) {
+        id -> Text,
+        name -> Text,
+        classname -> Nullable<Text>,
+        status -> Text,
+        duration -> Double,
+        message -> Nullable<Text>,
+        details -> Nullable<Text>,
+        suite_id -> Text,
+        queue_id -> Text,
+    }
+}
+
+table! {
+    test_suites (id) {
+        id -> Text,
+        name -> Text,
+        tests -> Integer,
+        failures -> Integer,
+        errors -> Integer,
+        skipped -> Integer,
+        duration -> Double,
+        queue_id -> Text,
     }
 }
 
@@ -47,12 +134,26 @@ table! {
     }
 }
 
+joinable!(builds -> repositories (repository_id));
+joinable!(caches -> repositories (repository_id));
+joinable!(polled_refs -> repositories (repository_id));
+joinable!(queue -> builds (build_id));
 joinable!(queue -> repositories (repository_id));
 joinable!(queue_logs -> queue (queue_id));
+joinable!(schedule_runs -> repositories (repository_id));
+joinable!(test_cases -> queue (queue_id));
+joinable!(test_cases -> test_suites (suite_id));
+joinable!(test_suites -> queue (queue_id));
 
 allow_tables_to_appear_in_same_query!(
+    builds,
+    caches,
+    polled_refs,
     queue,
     queue_logs,
     repositories,
+    schedule_runs,
+    test_cases,
+    test_suites,
     users,
 );
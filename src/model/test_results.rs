@@ -0,0 +1,340 @@
+use diesel::insert_into;
+use diesel::prelude::*;
+use failure::{format_err, Error};
+use serde_derive::Serialize;
+use std::collections::HashMap;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use schema::{test_cases, test_suites};
+
+use crate::queue::junit::{TestCase, TestStatus, TestSuite};
+use crate::queue::QueueItem;
+use crate::DbConnectionManager;
+
+use super::schema;
+
+#[derive(Identifiable, Queryable, Debug, Clone)]
+#[table_name = "test_suites"]
+struct TestSuiteRecord {
+	id: String,
+	name: String,
+	tests: i32,
+	failures: i32,
+	errors: i32,
+	skipped: i32,
+	duration: f64,
+	queue_id: String,
+}
+
+#[derive(Identifiable, Queryable, Debug, Clone)]
+#[table_name = "test_cases"]
+struct TestCaseRecord {
+	id: String,
+	name: String,
+	classname: Option<String>,
+	status: String,
+	duration: f64,
+	message: Option<String>,
+	details: Option<String>,
+	suite_id: String,
+	queue_id: String,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "test_suites"]
+struct NewTestSuiteRecord {
+	id: String,
+	name: String,
+	tests: i32,
+	failures: i32,
+	errors: i32,
+	skipped: i32,
+	duration: f64,
+	queue_id: String,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "test_cases"]
+struct NewTestCaseRecord {
+	id: String,
+	name: String,
+	classname: Option<String>,
+	status: String,
+	duration: f64,
+	message: Option<String>,
+	details: Option<String>,
+	suite_id: String,
+	queue_id: String,
+}
+
+impl From<TestCaseRecord> for TestCase {
+	fn from(record: TestCaseRecord) -> Self {
+		Self {
+			name: record.name,
+			classname: record.classname,
+			status: TestStatus::from(&*record.status),
+			duration: record.duration,
+			message: record.message,
+			details: record.details,
+		}
+	}
+}
+
+/// A stored test suite and its cases.
+#[derive(Serialize, Debug, Clone)]
+pub struct TestSuiteResult {
+	pub name: String,
+	pub tests: i32,
+	pub failures: i32,
+	pub errors: i32,
+	pub skipped: i32,
+	/// Duration in seconds
+	pub duration: f64,
+	pub cases: Vec<TestCase>,
+}
+
+/// Test results for a single job.
+#[derive(Serialize, Debug, Clone)]
+pub struct JobTestResults {
+	pub job_id: String,
+	pub tests: i32,
+	pub failures: i32,
+	pub errors: i32,
+	pub skipped: i32,
+	/// Duration in seconds
+	pub duration: f64,
+	pub suites: Vec<TestSuiteResult>,
+}
+
+/// A test which both passed and failed within the compared builds.
+#[derive(Serialize, Debug, Clone)]
+pub struct FlakyTest {
+	pub name: String,
+	pub classname: Option<String>,
+	pub passed: usize,
+	pub failed: usize,
+}
+
+/// Differences between a job's test results and those of previous builds on the same branch.
+#[derive(Serialize, Debug, Clone)]
+pub struct TestResultChanges {
+	pub job_id: String,
+	/// The build which `newly_failing` and `fixed` are compared against
+	pub previous_job_id: Option<String>,
+	/// Failed in this build but passed in the previous build
+	pub newly_failing: Vec<TestCase>,
+	/// Passed in this build but failed in the previous build
+	pub fixed: Vec<TestCase>,
+	/// Tests with mixed outcomes across this and recent builds
+	pub flaky: Vec<FlakyTest>,
+	/// Number of builds considered when looking for flaky tests
+	pub builds_compared: usize,
+}
+
+type TestKey = (Option<String>, String);
+
+fn test_key(case: &TestCase) -> TestKey {
+	(case.classname.clone(), case.name.clone())
+}
+
+#[derive(Debug)]
+pub struct TestResults {
+	connection_manager: DbConnectionManager,
+}
+
+impl TestResults {
+	pub fn new(connection_manager: DbConnectionManager) -> Self {
+		Self { connection_manager }
+	}
+
+	pub fn save(&self, job_id: &str, suites: &[TestSuite]) -> Result<(), Error> {
+		let connection = self.connection_manager.get_write();
+
+		let result = connection.transaction::<_, diesel::result::Error, _>(|| {
+			for suite in suites.iter() {
+				let suite_id = nanoid::custom(24, &crate::ALPHA_NUMERIC);
+
+				insert_into(test_suites::table)
+					.values(NewTestSuiteRecord {
+						id: suite_id.clone(),
+						name: suite.name.clone(),
+						tests: suite.cases.len() as i32,
+						failures: suite.count(TestStatus::Failed),
+						errors: suite.count(TestStatus::Errored),
+						skipped: suite.count(TestStatus::Skipped),
+						duration: suite.duration,
+						queue_id: job_id.to_owned(),
+					})
+					.execute(&*connection)?;
+
+				for case in suite.cases.iter() {
+					insert_into(test_cases::table)
+						.values(NewTestCaseRecord {
+							id: nanoid::custom(24, &crate::ALPHA_NUMERIC),
+							name: case.name.clone(),
+							classname: case.classname.clone(),
+							status: case.status.clone().into(),
+							duration: case.duration,
+							message: case.message.clone(),
+							details: case.details.clone(),
+							suite_id: suite_id.clone(),
+							queue_id: job_id.to_owned(),
+						})
+						.execute(&*connection)?;
+				}
+			}
+			Ok(())
+		});
+
+		match result {
+			Err(error) => Err(format_err!(
+				"Unable to save test results for {}. {}",
+				job_id,
+				error
+			)),
+			_ => Ok(()),
+		}
+	}
+
+	pub fn for_job(&self, job_id: &str) -> Result<JobTestResults, Error> {
+		let connection = self.connection_manager.get_read();
+
+		let suites = test_suites::table
+			.filter(test_suites::dsl::queue_id.eq(job_id))
+			.load::<TestSuiteRecord>(&connection);
+
+		let cases = test_cases::table
+			.filter(test_cases::dsl::queue_id.eq(job_id))
+			.load::<TestCaseRecord>(&connection);
+
+		let (suites, cases) = match (suites, cases) {
+			(Ok(suites), Ok(cases)) => (suites, cases),
+			(Err(error), _) | (_, Err(error)) => {
+				error!("Unable to fetch test results for {}. {}", job_id, error);
+				return Err(format_err!("Unable to fetch test results for {}.", job_id));
+			}
+		};
+
+		let mut cases_by_suite: HashMap<String, Vec<TestCase>> = HashMap::new();
+		for case in cases.into_iter() {
+			cases_by_suite
+				.entry(case.suite_id.clone())
+				.or_insert_with(Vec::new)
+				.push(TestCase::from(case));
+		}
+
+		let suites: Vec<TestSuiteResult> = suites
+			.into_iter()
+			.map(|suite| TestSuiteResult {
+				cases: cases_by_suite.remove(&suite.id).unwrap_or_default(),
+				name: suite.name,
+				tests: suite.tests,
+				failures: suite.failures,
+				errors: suite.errors,
+				skipped: suite.skipped,
+				duration: suite.duration,
+			})
+			.collect();
+
+		Ok(JobTestResults {
+			job_id: job_id.to_owned(),
+			tests: suites.iter().map(|s| s.tests).sum(),
+			failures: suites.iter().map(|s| s.failures).sum(),
+			errors: suites.iter().map(|s| s.errors).sum(),
+			skipped: suites.iter().map(|s| s.skipped).sum(),
+			duration: suites.iter().map(|s| s.duration).sum(),
+			suites,
+		})
+	}
+
+	fn cases_for_job(&self, job_id: &str) -> Result<Vec<TestCase>, Error> {
+		let cases = test_cases::table
+			.filter(test_cases::dsl::queue_id.eq(job_id))
+			.load::<TestCaseRecord>(&self.connection_manager.get_read())?;
+
+		Ok(cases.into_iter().map(TestCase::from).collect())
+	}
+
+	/// Compare the results of `job` with `previous_jobs`, which should be ordered from most to
+	/// least recent. Previous jobs without any test results are ignored.
+	pub fn changes(
+		&self,
+		job: &QueueItem,
+		previous_jobs: &[QueueItem],
+	) -> Result<TestResultChanges, Error> {
+		let current = self.cases_for_job(&job.id)?;
+
+		let mut history: Vec<(String, Vec<TestCase>)> = Vec::new();
+		for previous_job in previous_jobs.iter() {
+			let cases = self.cases_for_job(&previous_job.id)?;
+			if !cases.is_empty() {
+				history.push((previous_job.id.clone(), cases));
+			}
+		}
+
+		let mut newly_failing = Vec::new();
+		let mut fixed = Vec::new();
+		let previous_job_id = history.first().map(|(id, _)| id.clone());
+
+		if let Some((_, previous)) = history.first() {
+			let previous: HashMap<TestKey, &TestCase> =
+				previous.iter().map(|case| (test_key(case), case)).collect();
+
+			for case in current.iter() {
+				match previous.get(&test_key(case)) {
+					Some(previous_case) => {
+						if case.status.is_failure() && previous_case.status == TestStatus::Passed {
+							newly_failing.push(case.clone());
+						} else if case.status == TestStatus::Passed
+							&& previous_case.status.is_failure()
+						{
+							fixed.push(case.clone());
+						}
+					}
+					None => {
+						// New tests which fail straight away are also newly failing
+						if case.status.is_failure() {
+							newly_failing.push(case.clone());
+						}
+					}
+				}
+			}
+		}
+
+		let mut outcomes: HashMap<TestKey, (usize, usize)> = HashMap::new();
+		for case in current
+			.iter()
+			.chain(history.iter().flat_map(|(_, cases)| cases.iter()))
+		{
+			let entry = outcomes.entry(test_key(case)).or_insert((0, 0));
+			if case.status == TestStatus::Passed {
+				entry.0 += 1;
+			} else if case.status.is_failure() {
+				entry.1 += 1;
+			}
+		}
+
+		let mut flaky: Vec<FlakyTest> = outcomes
+			.into_iter()
+			.filter(|(_, (passed, failed))| *passed > 0 && *failed > 0)
+			.map(|((classname, name), (passed, failed))| FlakyTest {
+				name,
+				classname,
+				passed,
+				failed,
+			})
+			.collect();
+		flaky.sort_by(|a, b| b.failed.cmp(&a.failed).then_with(|| a.name.cmp(&b.name)));
+
+		Ok(TestResultChanges {
+			job_id: job.id.clone(),
+			previous_job_id,
+			newly_failing,
+			fixed,
+			flaky,
+			builds_compared: history.len() + 1,
+		})
+	}
+}
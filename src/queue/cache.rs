@@ -0,0 +1,354 @@
+use failure::{format_err, Error};
+use glob::glob;
+use regex::Regex;
+use sha3::{Digest, Sha3_256};
+use std::collections::HashMap;
+use std::fs::{self, create_dir_all, read, remove_dir_all};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use super::QueueItem;
+use crate::config::{AppConfig, CacheEntry};
+use crate::model::caches::{Cache, Caches};
+use crate::model::repositories::Repository;
+use crate::DbConnectionManager;
+
+/// A cache entry with its key rendered for a specific job.
+#[derive(Debug, Clone)]
+pub struct PreparedCache {
+	pub entry: CacheEntry,
+	pub key: String,
+	/// Whether a cache with the same key already existed and was restored into the working dir
+	pub restored: bool,
+}
+
+/// Hash the contents of all files matching `patterns`, which are relative to `workspace`.
+fn hash_files(patterns: &str, workspace: &Path) -> Result<String, Error> {
+	let mut paths: Vec<PathBuf> = Vec::new();
+	for pattern in patterns.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+		let pattern = workspace.join(pattern);
+		let pattern = pattern
+			.to_str()
+			.ok_or_else(|| format_err!("Invalid path {}", pattern.display()))?;
+		for path in glob(pattern)?.filter_map(Result::ok) {
+			paths.push(path);
+		}
+	}
+	paths.sort();
+
+	let mut hasher = Sha3_256::new();
+	for path in paths.iter().filter(|p| p.is_file()) {
+		hasher.input(path.strip_prefix(workspace).unwrap_or(path).to_string_lossy().as_bytes());
+		hasher.input(&read(path)?);
+	}
+
+	let hashed = hex::encode(hasher.result());
+	Ok(hashed[..16].to_owned())
+}
+
+/// Render a cache key template, replacing `{{ hash(...) }}` with a hash of the files and
+/// `{{ NAME }}` with the value of the variable `NAME`.
+pub fn render_key(
+	template: &str,
+	variables: &HashMap<String, String>,
+	workspace: &Path,
+) -> Result<String, Error> {
+	let expression_regex = Regex::new(r"\{\{\s*(.*?)\s*\}\}").unwrap();
+	let hash_regex = Regex::new(r"^hash\((.*)\)$").unwrap();
+
+	let mut key = String::new();
+	let mut last_match = 0;
+	for captures in expression_regex.captures_iter(template) {
+		let expression_match = captures.get(0).unwrap();
+		let expression = captures.get(1).unwrap().as_str();
+
+		key.push_str(&template[last_match..expression_match.start()]);
+		match hash_regex.captures(expression) {
+			Some(hash_captures) => key.push_str(&hash_files(&hash_captures[1], workspace)?),
+			None => match variables.get(expression) {
+				Some(value) => key.push_str(value),
+				None => warn!("Variable {} used in cache key is not set", expression),
+			},
+		}
+		last_match = expression_match.end();
+	}
+	key.push_str(&template[last_match..]);
+
+	Ok(key)
+}
+
+/// Only allow paths that stay inside of the working dir.
+fn is_safe_relative_path(path: &Path) -> bool {
+	path.components().all(|c| match c {
+		Component::Normal(_) | Component::CurDir => true,
+		_ => false,
+	})
+}
+
+/// Recursively copy `source` to `destination`, returning the number of bytes copied.
+fn copy_path(source: &Path, destination: &Path) -> io::Result<u64> {
+	let metadata = fs::symlink_metadata(source)?;
+
+	if let Some(parent) = destination.parent() {
+		create_dir_all(parent)?;
+	}
+
+	if metadata.is_dir() {
+		create_dir_all(destination)?;
+		let mut size = 0;
+		for entry in fs::read_dir(source)? {
+			let entry = entry?;
+			size += copy_path(&entry.path(), &destination.join(entry.file_name()))?;
+		}
+		Ok(size)
+	} else if metadata.file_type().is_symlink() {
+		copy_symlink(source, destination)?;
+		Ok(0)
+	} else {
+		fs::copy(source, destination)
+	}
+}
+
+#[cfg(unix)]
+fn copy_symlink(source: &Path, destination: &Path) -> io::Result<()> {
+	if fs::symlink_metadata(destination).is_ok() {
+		fs::remove_file(destination)?;
+	}
+	std::os::unix::fs::symlink(fs::read_link(source)?, destination)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(source: &Path, destination: &Path) -> io::Result<()> {
+	fs::copy(source, destination).map(|_| ())
+}
+
+#[derive(Debug)]
+pub struct CacheManager {
+	config: Arc<AppConfig>,
+	model: Caches,
+}
+
+impl CacheManager {
+	pub fn new(connection_manager: DbConnectionManager, config: Arc<AppConfig>) -> Self {
+		Self {
+			config,
+			model: Caches::new(connection_manager),
+		}
+	}
+
+	fn cache_dir(&self, cache_id: &str) -> PathBuf {
+		Path::new(&self.config.data_dir).join("caches").join(cache_id)
+	}
+
+	/// Render the keys of the repository's caches and restore any which already exist into
+	/// `workspace`.
+	pub fn restore(
+		&self,
+		repository: &Repository,
+		item: &QueueItem,
+		workspace: &Path,
+	) -> Vec<PreparedCache> {
+		let mut variables = repository.variables.clone();
+		variables.extend(item.data.inner().clone());
+		variables.extend(item.matrix.clone());
+
+		let mut prepared = Vec::new();
+		for entry in repository.caches.iter() {
+			let key = match render_key(&entry.key, &variables, workspace) {
+				Ok(key) => key,
+				Err(error) => {
+					error!("Unable to render cache key `{}`. {}", &entry.key, error);
+					continue;
+				}
+			};
+
+			let restored = match self.model.find_by_key(&repository.id, &key) {
+				Some(cache) => {
+					let cache_dir = self.cache_dir(&cache.id);
+					for path in entry.paths.iter().map(Path::new) {
+						if !is_safe_relative_path(path) || !cache_dir.join(path).exists() {
+							continue;
+						}
+						if let Err(error) = copy_path(&cache_dir.join(path), &workspace.join(path)) {
+							error!("Unable to restore {} from cache {}. {}", path.display(), &key, error);
+						}
+					}
+					if let Err(error) = self.model.touch(&cache) {
+						error!("{}", error);
+					}
+					info!("Restored cache {} for job {}", &key, &item.id);
+					true
+				}
+				None => {
+					debug!("No cache found for key {}", &key);
+					false
+				}
+			};
+
+			prepared.push(PreparedCache {
+				entry: entry.clone(),
+				key,
+				restored,
+			});
+		}
+		prepared
+	}
+
+	/// Save any caches whose key didn't exist before the build, then evict the least recently
+	/// used caches until the total size is within the configured limit.
+	pub fn save(&self, repository: &Repository, prepared: &[PreparedCache], workspace: &Path) {
+		for cache in prepared.iter().filter(|c| !c.restored) {
+			if let Err(error) = self.save_cache(repository, cache, workspace) {
+				error!("Unable to save cache {}. {}", &cache.key, error);
+			}
+		}
+
+		if let Err(error) = self.evict() {
+			error!("Unable to evict caches. {}", error);
+		}
+	}
+
+	fn save_cache(
+		&self,
+		repository: &Repository,
+		cache: &PreparedCache,
+		workspace: &Path,
+	) -> Result<(), Error> {
+		let staging_dir = self.cache_dir(&format!(
+			"staging-{}",
+			nanoid::custom(24, &crate::ALPHA_NUMERIC)
+		));
+		create_dir_all(&staging_dir)?;
+
+		let mut size = 0;
+		for path in cache.entry.paths.iter().map(Path::new) {
+			if !is_safe_relative_path(path) {
+				warn!("Not caching {}. Paths must be inside the working dir.", path.display());
+				continue;
+			}
+			if workspace.join(path).exists() {
+				match copy_path(&workspace.join(path), &staging_dir.join(path)) {
+					Ok(copied) => size += copied,
+					Err(error) => {
+						remove_dir_all(&staging_dir)?;
+						return Err(Error::from(error));
+					}
+				}
+			}
+		}
+
+		if let Some(max_size) = cache.entry.max_size {
+			if size > max_size * 1024 * 1024 {
+				remove_dir_all(&staging_dir)?;
+				return Err(format_err!(
+					"Cache is {} bytes which exceeds the limit of {}MB",
+					size,
+					max_size
+				));
+			}
+		}
+
+		match self.model.create(&repository.id, &cache.key, size as i64) {
+			Ok(record) => {
+				fs::rename(&staging_dir, self.cache_dir(&record.id))?;
+				info!("Saved cache {} ({} bytes)", &cache.key, size);
+				Ok(())
+			}
+			Err(error) => {
+				remove_dir_all(&staging_dir)?;
+				Err(error)
+			}
+		}
+	}
+
+	fn evict(&self) -> Result<(), Error> {
+		let caches = self.model.all()?;
+		let mut total_size: u64 = caches.iter().map(|c| c.size as u64).sum();
+
+		// Caches are ordered from least to most recently used
+		for cache in caches.iter() {
+			if total_size <= self.config.cache_size_limit {
+				break;
+			}
+			info!("Evicting cache {} ({} bytes)", &cache.cache_key, cache.size);
+			self.purge(cache)?;
+			total_size -= cache.size as u64;
+		}
+		Ok(())
+	}
+
+	/// Delete a cache and its files.
+	pub fn purge(&self, cache: &Cache) -> Result<(), Error> {
+		self.model.delete_by_id(&cache.id)?;
+		let cache_dir = self.cache_dir(&cache.id);
+		if cache_dir.exists() {
+			remove_dir_all(&cache_dir)?;
+		}
+		Ok(())
+	}
+
+	/// Delete all caches belonging to a repository.
+	pub fn purge_repository(&self, repository_id: &str) -> Result<(), Error> {
+		for cache in self.model.all_for_repository(repository_id)?.iter() {
+			self.purge(cache)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn workspace() -> PathBuf {
+		let workspace = std::env::temp_dir().join(format!(
+			"littleci-cache-{}",
+			nanoid::custom(24, &crate::ALPHA_NUMERIC)
+		));
+		create_dir_all(workspace.join("src")).unwrap();
+		workspace
+	}
+
+	#[test]
+	fn render_key_replaces_variables() {
+		let mut variables = HashMap::new();
+		variables.insert("LITTLECI_GIT_BRANCH".to_string(), "main".to_string());
+
+		let key = render_key(
+			"deps-{{LITTLECI_GIT_BRANCH}}-{{ MISSING }}-v1",
+			&variables,
+			Path::new("/nonexistent"),
+		)
+		.unwrap();
+		assert_eq!(key, "deps-main--v1");
+	}
+
+	#[test]
+	fn render_key_hashes_matching_files() {
+		let workspace = workspace();
+		fs::write(workspace.join("Cargo.lock"), "a").unwrap();
+		fs::write(workspace.join("src/main.rs"), "b").unwrap();
+		let variables = HashMap::new();
+
+		let key = render_key("deps-{{ hash(Cargo.lock) }}", &variables, &workspace).unwrap();
+		assert!(key.starts_with("deps-"));
+		assert_eq!(key.len(), "deps-".len() + 16);
+		assert_eq!(
+			render_key("deps-{{hash(Cargo.lock)}}", &variables, &workspace).unwrap(),
+			key
+		);
+
+		let both = render_key("{{ hash(Cargo.lock, src/*.rs) }}", &variables, &workspace).unwrap();
+		assert_ne!(format!("deps-{}", both), key);
+
+		fs::write(workspace.join("Cargo.lock"), "c").unwrap();
+		let changed = render_key("deps-{{ hash(Cargo.lock) }}", &variables, &workspace).unwrap();
+		assert_ne!(changed, key);
+
+		remove_dir_all(&workspace).unwrap();
+	}
+}
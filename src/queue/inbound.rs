@@ -0,0 +1,181 @@
+use failure::{format_err, Error};
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use crate::config::InboundWebhook;
+
+fn validate_pointer(pointer: &str) -> Result<(), Error> {
+	if pointer.is_empty() || pointer.starts_with('/') {
+		Ok(())
+	} else {
+		Err(format_err!(
+			"Invalid JSON Pointer `{}`. Pointers must start with `/`",
+			pointer
+		))
+	}
+}
+
+/// Check that the JSON Pointers and filter patterns are valid.
+pub fn validate(webhook: &InboundWebhook) -> Result<(), Error> {
+	for pointer in webhook.variables.values() {
+		validate_pointer(pointer)?;
+	}
+
+	for filter in webhook.filters.iter() {
+		validate_pointer(&filter.pointer)?;
+		if let Err(error) = Regex::new(&filter.pattern) {
+			return Err(format_err!(
+				"Invalid pattern `{}`. {}",
+				filter.pattern,
+				error
+			));
+		}
+	}
+
+	if let Some(signature) = &webhook.signature {
+		if signature.header.is_empty() {
+			return Err(format_err!("Signature header is missing"));
+		}
+	}
+
+	Ok(())
+}
+
+/// The value at a JSON Pointer as a variable. Strings are used as they are, other values as JSON.
+fn pointer_value(payload: &Value, pointer: &str) -> Option<String> {
+	match payload.pointer(pointer) {
+		None | Some(Value::Null) => None,
+		Some(Value::String(value)) => Some(value.clone()),
+		Some(value) => Some(value.to_string()),
+	}
+}
+
+/// The reason a payload should be skipped, if any of the filters don't match.
+pub fn filter_mismatch(webhook: &InboundWebhook, payload: &Value) -> Option<String> {
+	for filter in webhook.filters.iter() {
+		let value = match pointer_value(payload, &filter.pointer) {
+			Some(value) => value,
+			None => return Some(format!("`{}` is missing", &filter.pointer)),
+		};
+
+		match Regex::new(&filter.pattern) {
+			Ok(pattern) => {
+				if !pattern.is_match(&value) {
+					return Some(format!(
+						"`{}` didn't match `{}`",
+						&filter.pointer, &filter.pattern
+					));
+				}
+			}
+			Err(error) => {
+				error!(
+					"Invalid inbound filter pattern `{}`. {}",
+					&filter.pattern, error
+				);
+				return Some(format!("Invalid pattern `{}`", &filter.pattern));
+			}
+		}
+	}
+
+	None
+}
+
+/// Extract the mapped variables from a payload. Variables whose values are missing are left out.
+pub fn extract(webhook: &InboundWebhook, payload: &Value) -> HashMap<String, String> {
+	webhook
+		.variables
+		.iter()
+		.filter_map(|(name, pointer)| pointer_value(payload, pointer).map(|v| (name.clone(), v)))
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	fn webhook(json: &str) -> InboundWebhook {
+		serde_json::from_str(json).unwrap()
+	}
+
+	#[test]
+	fn validate_rejects_pointers_without_a_leading_slash() {
+		assert!(validate(&webhook(r#"{"variables": {"TAG": "/push_data/tag"}}"#)).is_ok());
+		assert!(validate(&webhook(r#"{"variables": {"TAG": "push_data/tag"}}"#)).is_err());
+		assert!(validate(&webhook(
+			r#"{"filters": [{"pointer": "action", "pattern": "^published$"}]}"#
+		))
+		.is_err());
+	}
+
+	#[test]
+	fn validate_rejects_invalid_patterns_and_empty_signature_headers() {
+		assert!(validate(&webhook(
+			r#"{"filters": [{"pointer": "/action", "pattern": "(published"}]}"#
+		))
+		.is_err());
+		assert!(validate(&webhook(r#"{"signature": {"header": ""}}"#)).is_err());
+		assert!(validate(&webhook(r#"{"signature": {"header": "X-Signature"}}"#)).is_ok());
+	}
+
+	#[test]
+	fn extract_leaves_out_missing_values_and_serializes_non_strings() {
+		let webhook = webhook(
+			r#"{"variables": {
+				"TAG": "/push_data/tag",
+				"PUSHER": "/push_data/pusher",
+				"SIZE": "/push_data/size",
+				"PRIVATE": "/repository/is_private",
+				"LABELS": "/repository/labels"
+			}}"#,
+		);
+		let payload = json!({
+			"push_data": {"tag": "latest", "pusher": null, "size": 42},
+			"repository": {"is_private": false, "labels": ["a", "b"]}
+		});
+
+		let variables = extract(&webhook, &payload);
+
+		assert_eq!(variables.len(), 4);
+		assert_eq!(variables["TAG"], "latest");
+		assert_eq!(variables["SIZE"], "42");
+		assert_eq!(variables["PRIVATE"], "false");
+		assert_eq!(variables["LABELS"], r#"["a","b"]"#);
+		assert!(!variables.contains_key("PUSHER"));
+	}
+
+	#[test]
+	fn filter_mismatch_reports_missing_and_mismatched_values() {
+		let webhook = webhook(
+			r#"{"filters": [
+				{"pointer": "/action", "pattern": "^published$"},
+				{"pointer": "/release/draft", "pattern": "^false$"}
+			]}"#,
+		);
+
+		let matching = json!({"action": "published", "release": {"draft": false}});
+		assert_eq!(filter_mismatch(&webhook, &matching), None);
+
+		let mismatched = json!({"action": "unpublished", "release": {"draft": false}});
+		assert_eq!(
+			filter_mismatch(&webhook, &mismatched),
+			Some("`/action` didn't match `^published$`".into())
+		);
+
+		let draft = json!({"action": "published", "release": {"draft": true}});
+		assert_eq!(
+			filter_mismatch(&webhook, &draft),
+			Some("`/release/draft` didn't match `^false$`".into())
+		);
+
+		let missing = json!({"action": "published"});
+		assert_eq!(
+			filter_mismatch(&webhook, &missing),
+			Some("`/release/draft` is missing".into())
+		);
+	}
+}
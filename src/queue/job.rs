@@ -1,18 +1,30 @@
 use reqwest::Client;
 use serde::Serialize;
 use serde_json::to_string as to_json_string;
+use std::collections::HashMap;
 use std::convert::From;
 use std::fmt::Debug;
-use std::fs::{create_dir_all, File};
-use std::process::{Command, Stdio};
+use parking_lot::Mutex;
+use std::fs::{create_dir_all, read, read_to_string, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
 use std::thread;
+use std::time::Duration;
 
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
 
-use super::{ExecutionStatus, QueueItem, QueueService, ServiceState};
+use super::cache::CacheManager;
+use super::junit;
+use super::preprocess;
+use super::steps::{self, StepState};
+use super::{ArbitraryData, ExecutionStatus, QueueItem, QueueService, ServiceState};
+use crate::config::{Step, Upstream, UpstreamStatus};
+use crate::model::builds::Builds;
 use crate::model::queues::Queues;
 use crate::model::repositories::{Repositories, Repository};
+use crate::model::test_results::TestResults;
 
 #[derive(Serialize, Debug, Clone)]
 pub struct QueueItemData {
@@ -25,6 +37,18 @@ pub struct QueueItemData {
 	/// Current status of the execution
 	#[serde(flatten)]
 	pub status: ExecutionStatus,
+
+	/// Key/value pairs exported by the command
+	pub outputs: HashMap<String, String>,
+
+	/// Build which the job belongs to if the repository has a build matrix
+	pub build_id: Option<String>,
+
+	/// Matrix values the job was run with
+	pub matrix: HashMap<String, String>,
+
+	/// Job of an upstream repository which triggered this job
+	pub upstream_job_id: Option<String>,
 }
 
 impl From<QueueItem> for QueueItemData {
@@ -33,14 +57,55 @@ impl From<QueueItem> for QueueItemData {
 			id: queue_item.id,
 			repository: queue_item.repository_id,
 			status: queue_item.status.clone(),
+			outputs: queue_item.outputs,
+			build_id: queue_item.build_id,
+			matrix: queue_item.matrix,
+			upstream_job_id: queue_item.upstream_job_id,
 		}
 	}
 }
 
 const SUCCESS_EXIT_CODE: i32 = 0;
 
+const OUTPUT_FILE_NAME: &str = "outputs.env";
+
+const SUMMARY_FILE_NAME: &str = "summary.md";
+
+const APPROVAL_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Parse `key=value` lines written to the `LITTLECI_OUTPUT` file. Empty lines, comments and lines
+/// without a `=` are ignored. If a key is written more than once the last value wins.
+fn read_outputs(path: &str) -> HashMap<String, String> {
+	let mut outputs = HashMap::new();
+
+	let contents = match read_to_string(path) {
+		Ok(contents) => contents,
+		// The command didn't write any outputs
+		Err(_) => return outputs,
+	};
+
+	for line in contents.lines() {
+		let line = line.trim_start();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+
+		let mut parts = line.splitn(2, '=');
+		match (parts.next(), parts.next()) {
+			(Some(key), Some(value)) if !key.trim().is_empty() => {
+				outputs.insert(key.trim().to_owned(), value.to_owned());
+			}
+			_ => warn!("Ignoring invalid output line `{}`", line),
+		}
+	}
+
+	outputs
+}
+
 pub trait JobRunner: Debug + Send + Sync {
-	fn preprocess(&self, queue_service: QueueService);
+	/// Called before a queued job is started. Setting the status of the job to skipped prevents
+	/// it from running.
+	fn preprocess(&self, repository: &Repository, item: &mut QueueItem);
 	fn process(&self, queue_service: QueueService);
 }
 
@@ -48,8 +113,11 @@ pub trait JobRunner: Debug + Send + Sync {
 pub struct CommandRunner;
 
 impl JobRunner for CommandRunner {
-	fn preprocess(&self, queue_service: QueueService) {
-
+	fn preprocess(&self, repository: &Repository, item: &mut QueueItem) {
+		if let Some(reason) = preprocess::skip_reason(repository, item) {
+			info!("Skipping execution {}. {}", &item.id, &reason);
+			item.status = ExecutionStatus::Skipped(reason);
+		}
 	}
 
 	// TODO break this up
@@ -94,6 +162,13 @@ impl JobRunner for CommandRunner {
 											&repository.id,
 										);
 										// Clean up
+										let cache_manager = CacheManager::new(
+											queue_service.connection_manager.clone(),
+											queue_service.config.clone(),
+										);
+										if let Err(error) = cache_manager.purge_repository(&repository.id) {
+											error!("Unable to remove caches. {}", error);
+										}
 										match repository_model
 											.actually_delete_repository(&repository.id)
 										{
@@ -122,6 +197,21 @@ impl JobRunner for CommandRunner {
 
 							match item {
 								Some(mut item) => {
+									queue_service.runner.preprocess(&repository, &mut item);
+									if let ExecutionStatus::Skipped(_) = item.status {
+										if let Err(error) = queue_model.update_status(&item) {
+											error!(
+												"Unable to update status of job {}. {}",
+												&item.id, error
+											);
+										}
+										call_webhooks(&repository, &item);
+										report_status(&queue_service, &repository, &item);
+										// A skipped job may be the last one of its build
+										trigger_downstream(&queue_service, &repository, &item);
+										continue;
+									}
+
 									info!("Starting execution {}", &item.id);
 									item.status = ExecutionStatus::Running;
 
@@ -133,6 +223,7 @@ impl JobRunner for CommandRunner {
 									}
 
 									call_webhooks(&repository, &item);
+									report_status(&queue_service, &repository, &item);
 
 									let execution_dir = format!(
 										"{}/jobs/{}",
@@ -151,82 +242,82 @@ impl JobRunner for CommandRunner {
 												},
 											};
 
-											let stderr_log_f = stdout_log_f.try_clone();
+											let mut variables = repository.variables.clone();
+											variables.extend(item.data.inner().clone());
+											variables.extend(item.matrix.clone());
 
-											let stderr_log_f = match stderr_log_f {
-												Ok(stdio) => stdio,
-												_ => {
-													error!("Unable to create stderr log file");
-													return
-												},
+											if let Some(build_id) = &item.build_id {
+												variables.insert("LITTLECI_BUILD_ID".into(), build_id.clone());
+											}
+
+											let output_path = format!("{}/{}", &execution_dir, OUTPUT_FILE_NAME);
+											variables.insert("LITTLECI_OUTPUT".into(), output_path.clone());
+											variables.insert(
+												"LITTLECI_SUMMARY".into(),
+												format!("{}/{}", &execution_dir, SUMMARY_FILE_NAME),
+											);
+
+											let workspace = repository
+												.working_dir
+												.clone()
+												.unwrap_or_else(|| queue_service.config.working_dir.clone());
+
+											let cache_manager = CacheManager::new(
+												queue_service.connection_manager.clone(),
+												queue_service.config.clone(),
+											);
+											let caches = cache_manager.restore(&repository, &item, Path::new(&workspace));
+
+											item.status = if repository.steps.is_empty() {
+												let status = run_command(
+													&repository.run,
+													&variables,
+													&repository.working_dir,
+													stdout_log_f,
+												);
+												execution_status(&item.id, status)
+											} else {
+												run_steps(
+													&queue_service,
+													&queue_model,
+													&repository,
+													&mut item,
+													variables.clone(),
+													&execution_dir,
+													stdout_log_f,
+												)
 											};
 
-											let mut command = Command::new("/bin/sh");
+											run_hooks(&repository, &item, &variables, &execution_dir);
 
-											for variable in repository.variables.iter() {
-												let (key, value) = variable;
-												command.env(key, value);
+											item.outputs = read_outputs(&output_path);
+											if let Err(error) = queue_model.update_outputs(&item) {
+												error!("Unable to save outputs of job {}. {}", &item.id, error);
 											}
 
-											let data = &item.data.inner();
-											for (key, value) in data.iter() {
-												command.env(key, value);
+											if !repository.test_reports.is_empty() {
+												let suites = junit::collect(&repository.test_reports, &workspace);
+												let test_results = TestResults::new(queue_service.connection_manager.clone());
+												if let Err(error) = test_results.save(&item.id, &suites) {
+													error!("{}", error);
+												}
 											}
 
-											if let Some(working_dir) = &repository.working_dir {
-												command.current_dir(working_dir.to_owned());
-											};
+											if let Err(error) = queue_model.update_status(&item) {
+												error!("Unable to update status of job {}. {}", &item.id, error);
+											}
 
-											command
-												.args(&["-c", &repository.run.to_string()])
-												.stdout(Stdio::from(stdout_log_f))
-												.stderr(Stdio::from(stderr_log_f));
-
-											let status = command.status();
-
-											match status {
-												Ok(status) => {
-													match status.code() {
-														Some(code) => {
-															match code {
-																code if code != SUCCESS_EXIT_CODE => {
-																	item.status = ExecutionStatus::Failed(code);
-																	if let Err(error) = queue_model.update_status(&item) {
-																		error!("Unable to update status of job {}. {}", &item.id, error);
-																	}
-																	error!("Exection {} failed with code {}", &item.id, code)
-																},
-																_ => {
-																	item.status = ExecutionStatus::Completed;
-																	if let Err(error) = queue_model.update_status(&item) {
-																		error!("Unable to update status of item {}. {}", &item.id, error);
-																	}
-																	info!("Execution {} completed successfully", &item.id)
-																},
-															}
-														},
-														None => {
-															item.status = ExecutionStatus::Cancelled;
-															if let Err(error) = queue_model.update_status(&item) {
-																error!("Unable to update status of item {}. {}", &item.id, error);
-															}
-															info!("Exection {} terminated by signal", &item.id)
-														},
-													}
-												},
-												Err(error) => {
-													item.status = ExecutionStatus::Failed(-1);
-													if let Err(error) = queue_model.update_status(&item) {
-														error!("Unable to update status of item {}. {}", &item.id, error);
-													}
-													error!("Execution {} failed. Unable to launch script. Error: {}", &item.id, error)
-												},
+											// Only save caches from successful builds
+											if let ExecutionStatus::Completed = item.status {
+												cache_manager.save(&repository, &caches, Path::new(&workspace));
 											}
 										},
 										Err(_) => error!("Execution {} failed. Unable to create log dir. Please check permissions.", &item.id),
 									}
 
 									call_webhooks(&repository, &item);
+									report_status(&queue_service, &repository, &item);
+									trigger_downstream(&queue_service, &repository, &item);
 								}
 								// We've processed all the items in this queue and can exit
 								None => break,
@@ -249,6 +340,219 @@ impl JobRunner for CommandRunner {
 	}
 }
 
+/// Run `run` with `/bin/sh`, writing stdout and stderr to `log`.
+fn run_command(
+	run: &str,
+	variables: &HashMap<String, String>,
+	working_dir: &Option<String>,
+	log: File,
+) -> io::Result<ExitStatus> {
+	let mut command = Command::new("/bin/sh");
+	command.envs(variables.iter());
+
+	if let Some(working_dir) = working_dir {
+		command.current_dir(working_dir.to_owned());
+	};
+
+	command
+		.args(&["-c", run])
+		.stdout(Stdio::from(log.try_clone()?))
+		.stderr(Stdio::from(log))
+		.status()
+}
+
+/// Convert the exit status of a command to the status of the execution it belongs to.
+fn execution_status(execution: &str, status: io::Result<ExitStatus>) -> ExecutionStatus {
+	match status {
+		Ok(status) => match status.code() {
+			Some(code) if code != SUCCESS_EXIT_CODE => {
+				error!("Exection {} failed with code {}", execution, code);
+				ExecutionStatus::Failed(code)
+			}
+			Some(_) => {
+				info!("Execution {} completed successfully", execution);
+				ExecutionStatus::Completed
+			}
+			None => {
+				info!("Exection {} terminated by signal", execution);
+				ExecutionStatus::Cancelled
+			}
+		},
+		Err(error) => {
+			error!(
+				"Execution {} failed. Unable to launch script. Error: {}",
+				execution, error
+			);
+			ExecutionStatus::Failed(-1)
+		}
+	}
+}
+
+/// Run the repository's steps as a dependency graph. Each step logs to its own file, which is
+/// appended to the job's log once the step has finished.
+fn run_steps(
+	queue_service: &QueueService,
+	queue_model: &Queues,
+	repository: &Repository,
+	item: &mut QueueItem,
+	variables: HashMap<String, String>,
+	execution_dir: &str,
+	log: File,
+) -> ExecutionStatus {
+	item.steps = repository.steps.iter().map(StepState::from).collect();
+
+	if let Err(error) = steps::validate(&repository.steps) {
+		error!("Unable to run steps for job {}. {}", &item.id, error);
+		return ExecutionStatus::Failed(-1);
+	}
+
+	let steps_dir = format!("{}/steps", execution_dir);
+	if let Err(error) = create_dir_all(&steps_dir) {
+		error!("Unable to create step log dir. {}", error);
+		return ExecutionStatus::Failed(-1);
+	}
+
+	let job_id = item.id.clone();
+	let working_dir = repository.working_dir.clone();
+	let log = Mutex::new(log);
+
+	let run_step = move |step: &Step| {
+		let execution = format!("{} step {}", &job_id, &step.name);
+		let step_log_path = format!("{}/{}.log", &steps_dir, &step.name);
+
+		let status = File::create(&step_log_path)
+			.and_then(|step_log| run_command(&step.run, &variables, &working_dir, step_log));
+		let status = execution_status(&execution, status);
+
+		// Append the whole step to the job's log so that parallel steps don't interleave
+		let step_output = read(&step_log_path).unwrap_or_default();
+		let mut log = log.lock();
+		let result = writeln!(log, "==> {}", &step.name)
+			.and_then(|_| log.write_all(&step_output))
+			.and_then(|_| writeln!(log));
+		if let Err(error) = result {
+			error!("Unable to write log for {}. {}", &execution, error);
+		}
+
+		status
+	};
+
+	let approval_service = queue_service.clone();
+	let approval_repository = repository.clone();
+	let job_id = item.id.clone();
+	let await_approval = move |step: &Step| {
+		wait_for_approval(&approval_service, &approval_repository, &job_id, &step.name)
+	};
+
+	let states = steps::execute(
+		&repository.steps,
+		queue_service.config.max_parallel_steps,
+		run_step,
+		await_approval,
+		|states| {
+			item.steps = states.to_vec();
+			if let Err(error) = queue_model.update_steps(&item) {
+				error!("{}", error);
+			}
+		},
+	);
+
+	item.steps = states;
+	if let Err(error) = queue_model.update_steps(&item) {
+		error!("{}", error);
+	}
+	steps::combined_status(&item.steps)
+}
+
+/// Mark the job as awaiting approval and wait until a user approves it, which sets it back to
+/// running, or rejects it.
+fn wait_for_approval(
+	queue_service: &QueueService,
+	repository: &Repository,
+	job_id: &str,
+	step: &str,
+) -> bool {
+	let queue_model = Queues::new(queue_service.connection_manager.clone());
+
+	match queue_model.find_by_id(job_id) {
+		Some(mut job) => {
+			// Parallel steps may already be waiting for the job to be approved
+			if let ExecutionStatus::Running = job.status {
+				job.status = ExecutionStatus::AwaitingApproval;
+				if let Err(error) = queue_model.update_status(&job) {
+					error!("Unable to update status of job {}. {}", job_id, error);
+					return false;
+				}
+				report_status(queue_service, repository, &job);
+			}
+		}
+		None => {
+			error!("Could not find job {}", job_id);
+			return false;
+		}
+	}
+
+	info!("Job {} is waiting for step {} to be approved", job_id, step);
+	loop {
+		thread::sleep(APPROVAL_POLL_INTERVAL);
+
+		match queue_model.find_by_id(job_id).map(|job| job.status) {
+			Some(ExecutionStatus::AwaitingApproval) => continue,
+			Some(ExecutionStatus::Running) => return true,
+			_ => return false,
+		}
+	}
+}
+
+/// Run the repository's `after_success` or `after_failure` command followed by `always`. Their
+/// output is appended to the job's log and they don't change the status of the job.
+fn run_hooks(
+	repository: &Repository,
+	item: &QueueItem,
+	variables: &HashMap<String, String>,
+	execution_dir: &str,
+) {
+	let on_status = match item.status {
+		ExecutionStatus::Completed => ("after_success", &repository.after_success),
+		_ => ("after_failure", &repository.after_failure),
+	};
+	let hooks = vec![on_status, ("always", &repository.always)];
+
+	let (job_status, _): (String, Option<String>) = item.status.clone().into();
+	let mut variables = variables.clone();
+	variables.insert("LITTLECI_JOB_STATUS".into(), job_status);
+
+	for (name, hook) in hooks.into_iter() {
+		let hook = match hook {
+			Some(hook) if !hook.trim().is_empty() => hook,
+			_ => continue,
+		};
+
+		let log = OpenOptions::new()
+			.append(true)
+			.create(true)
+			.open(format!("{}/output.log", execution_dir))
+			.and_then(|mut log| writeln!(log, "==> {}", name).map(|_| log));
+
+		let log = match log {
+			Ok(log) => log,
+			Err(error) => {
+				error!("Unable to open log for {} of job {}. {}", name, &item.id, error);
+				continue;
+			}
+		};
+
+		let execution = format!("{} {}", &item.id, name);
+		match execution_status(
+			&execution,
+			run_command(hook, &variables, &repository.working_dir, log),
+		) {
+			ExecutionStatus::Completed => {}
+			status => warn!("{} of job {} didn't complete: {:?}", name, &item.id, status),
+		}
+	}
+}
+
 fn call_webhooks(repository: &Repository, item: &QueueItem) {
 	let client = Client::new();
 	match to_json_string(&QueueItemData::from(item.clone())) {
@@ -265,3 +569,175 @@ fn call_webhooks(repository: &Repository, item: &QueueItem) {
 		Err(error) => error!("Unable to serialize job data. {}", error),
 	}
 }
+
+fn report_status(queue_service: &QueueService, repository: &Repository, item: &QueueItem) {
+	queue_service
+		.reporter
+		.report(&queue_service.config, repository, item);
+}
+
+fn upstream_triggers(upstream: &Upstream, status: &ExecutionStatus) -> bool {
+	upstream.on.iter().any(|on| match (on, status) {
+		(UpstreamStatus::Completed, ExecutionStatus::Completed) => true,
+		(UpstreamStatus::Failed, ExecutionStatus::Failed(_)) => true,
+		(UpstreamStatus::Any, ExecutionStatus::Completed)
+		| (UpstreamStatus::Any, ExecutionStatus::Failed(_))
+		| (UpstreamStatus::Any, ExecutionStatus::Cancelled) => true,
+		_ => false,
+	})
+}
+
+/// Repositories of `item` and the upstream jobs which led to it.
+fn upstream_repositories(queue_model: &Queues, item: &QueueItem) -> Vec<String> {
+	let mut repository_ids = vec![item.repository_id.clone()];
+	let mut upstream_job_id = item.upstream_job_id.clone();
+
+	while let Some(job_id) = upstream_job_id {
+		match queue_model.find_by_id(&job_id) {
+			Some(job) => {
+				if repository_ids.contains(&job.repository_id) {
+					break;
+				}
+				repository_ids.push(job.repository_id);
+				upstream_job_id = job.upstream_job_id;
+			}
+			None => break,
+		}
+	}
+	repository_ids
+}
+
+/// Queue jobs for any repositories which list this repository as upstream. Jobs belonging to a
+/// build only trigger downstream repositories once every job in the build has finished, using
+/// the combined status of the build.
+fn trigger_downstream(queue_service: &QueueService, repository: &Repository, item: &QueueItem) {
+	let status = match &item.build_id {
+		Some(build_id) => {
+			match Builds::new(queue_service.connection_manager.clone()).find(&repository.id, build_id) {
+				Ok(build) => build.status,
+				Err(error) => {
+					error!("Unable to check status of build {}. {}", build_id, error);
+					return;
+				}
+			}
+		}
+		None => item.status.clone(),
+	};
+
+	match status {
+		ExecutionStatus::Queued | ExecutionStatus::AwaitingApproval | ExecutionStatus::Running => {
+			return
+		}
+		_ => {}
+	}
+
+	let downstream: Vec<(Repository, Upstream)> =
+		Repositories::new(queue_service.connection_manager.clone())
+			.all()
+			.into_iter()
+			.filter_map(|downstream| {
+				let upstream = downstream
+					.upstream
+					.iter()
+					.find(|u| u.repository == repository.slug && upstream_triggers(u, &status))
+					.cloned();
+				upstream.map(|upstream| (downstream, upstream))
+			})
+			.collect();
+
+	if downstream.is_empty() {
+		return;
+	}
+
+	let manager = match queue_service.manager() {
+		Some(manager) => manager,
+		None => {
+			warn!("Queue manager has shut down. Not triggering downstream repositories.");
+			return;
+		}
+	};
+
+	let queue_model = Queues::new(queue_service.connection_manager.clone());
+	let triggered_by = upstream_repositories(&queue_model, item);
+
+	for (downstream, upstream) in downstream.iter() {
+		// Prevent cycles between repositories from queueing jobs forever
+		if triggered_by.contains(&downstream.id) {
+			warn!(
+				"Not triggering {} from job {}. It's already upstream of this job.",
+				&downstream.slug, &item.id
+			);
+			continue;
+		}
+
+		let mut data = HashMap::new();
+		for key in upstream.pass_through.iter() {
+			// Outputs written by the upstream job take precedence over its data
+			if let Some(value) = item.outputs.get(key).or_else(|| item.data.inner().get(key)) {
+				data.insert(key.clone(), value.clone());
+			}
+		}
+		data.insert("LITTLECI_UPSTREAM_JOB_ID".into(), item.id.clone());
+		data.insert("LITTLECI_UPSTREAM_REPOSITORY".into(), repository.slug.clone());
+		if let Some(build_id) = &item.build_id {
+			data.insert("LITTLECI_UPSTREAM_BUILD_ID".into(), build_id.clone());
+		}
+
+		match manager.push_downstream(&downstream.slug, ArbitraryData::new(data), item) {
+			Ok(job) => info!(
+				"Job {} triggered job {} for downstream repository {}",
+				&item.id, &job.id, &downstream.slug
+			),
+			Err(error) => error!(
+				"Unable to trigger downstream repository {}. {}",
+				&downstream.slug, error
+			),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::fs::{remove_file, write};
+
+	fn outputs(contents: &str) -> HashMap<String, String> {
+		let path = std::env::temp_dir().join(format!(
+			"littleci-outputs-{}.env",
+			nanoid::custom(24, &crate::ALPHA_NUMERIC)
+		));
+		let path = path.to_str().unwrap();
+		write(path, contents).unwrap();
+		let outputs = read_outputs(path);
+		remove_file(path).unwrap();
+		outputs
+	}
+
+	#[test]
+	fn read_outputs_parses_key_value_lines() {
+		let outputs = outputs(
+			"# Written by the build\nVERSION=1.2.0\n\n  ARTIFACT = dist/app.tar.gz\nURL=https://example.com/?a=b\n",
+		);
+
+		let mut expected = HashMap::new();
+		expected.insert("VERSION".to_string(), "1.2.0".to_string());
+		expected.insert("ARTIFACT".to_string(), " dist/app.tar.gz".to_string());
+		expected.insert("URL".to_string(), "https://example.com/?a=b".to_string());
+		assert_eq!(outputs, expected);
+	}
+
+	#[test]
+	fn read_outputs_ignores_invalid_lines_and_keeps_the_last_value() {
+		let outputs = outputs("no separator\n=value\nKEY=first\nKEY=second\nEMPTY=\n");
+
+		let mut expected = HashMap::new();
+		expected.insert("KEY".to_string(), "second".to_string());
+		expected.insert("EMPTY".to_string(), "".to_string());
+		assert_eq!(outputs, expected);
+	}
+
+	#[test]
+	fn read_outputs_without_a_file() {
+		assert!(read_outputs("/nonexistent/littleci/outputs.env").is_empty());
+	}
+}
@@ -0,0 +1,273 @@
+use failure::{format_err, Error};
+use glob::glob;
+use roxmltree::{Document, Node};
+use serde::{Deserialize, Serialize};
+use std::fs::read_to_string;
+use std::path::Path;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TestStatus {
+	#[serde(rename = "passed")]
+	Passed,
+	#[serde(rename = "failed")]
+	Failed,
+	#[serde(rename = "errored")]
+	Errored,
+	#[serde(rename = "skipped")]
+	Skipped,
+}
+
+impl TestStatus {
+	pub fn is_failure(&self) -> bool {
+		match self {
+			TestStatus::Failed | TestStatus::Errored => true,
+			_ => false,
+		}
+	}
+}
+
+impl From<&str> for TestStatus {
+	fn from(status: &str) -> Self {
+		match status {
+			"failed" => TestStatus::Failed,
+			"errored" => TestStatus::Errored,
+			"skipped" => TestStatus::Skipped,
+			_ => TestStatus::Passed,
+		}
+	}
+}
+
+impl Into<String> for TestStatus {
+	fn into(self) -> String {
+		match self {
+			TestStatus::Passed => "passed".into(),
+			TestStatus::Failed => "failed".into(),
+			TestStatus::Errored => "errored".into(),
+			TestStatus::Skipped => "skipped".into(),
+		}
+	}
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TestCase {
+	pub name: String,
+	pub classname: Option<String>,
+	pub status: TestStatus,
+	/// Duration in seconds
+	pub duration: f64,
+	/// Message attribute of the `<failure>`, `<error>` or `<skipped>` element
+	pub message: Option<String>,
+	/// Text content of the `<failure>`, `<error>` or `<skipped>` element
+	pub details: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TestSuite {
+	pub name: String,
+	/// Duration in seconds
+	pub duration: f64,
+	pub cases: Vec<TestCase>,
+}
+
+impl TestSuite {
+	pub fn count(&self, status: TestStatus) -> i32 {
+		self.cases.iter().filter(|c| c.status == status).count() as i32
+	}
+}
+
+fn parse_duration(node: &Node) -> f64 {
+	node.attribute("time")
+		.and_then(|time| time.replace(",", "").parse().ok())
+		.unwrap_or(0.0)
+}
+
+fn parse_test_case(node: &Node) -> TestCase {
+	let mut status = TestStatus::Passed;
+	let mut message = None;
+	let mut details = None;
+
+	for child in node.children().filter(|n| n.is_element()) {
+		let child_status = match child.tag_name().name() {
+			"failure" => TestStatus::Failed,
+			"error" => TestStatus::Errored,
+			"skipped" => TestStatus::Skipped,
+			_ => continue,
+		};
+		status = child_status;
+		message = child.attribute("message").map(String::from);
+		details = child.text().map(|t| t.trim().to_owned()).filter(|t| !t.is_empty());
+	}
+
+	TestCase {
+		name: node.attribute("name").unwrap_or_default().to_owned(),
+		classname: node.attribute("classname").map(String::from),
+		status,
+		duration: parse_duration(node),
+		message,
+		details,
+	}
+}
+
+fn parse_test_suites(node: &Node, suites: &mut Vec<TestSuite>) {
+	match node.tag_name().name() {
+		"testsuite" => {
+			let cases: Vec<TestCase> = node
+				.children()
+				.filter(|n| n.is_element() && n.tag_name().name() == "testcase")
+				.map(|n| parse_test_case(&n))
+				.collect();
+
+			if !cases.is_empty() {
+				suites.push(TestSuite {
+					name: node.attribute("name").unwrap_or_default().to_owned(),
+					duration: parse_duration(node),
+					cases,
+				});
+			}
+
+			// Some reporters nest suites inside of suites
+			for child in node.children().filter(|n| n.is_element()) {
+				parse_test_suites(&child, suites);
+			}
+		}
+		"testsuites" => {
+			for child in node.children().filter(|n| n.is_element()) {
+				parse_test_suites(&child, suites);
+			}
+		}
+		_ => {}
+	}
+}
+
+/// Parse a single JUnit XML document.
+pub fn parse(xml: &str) -> Result<Vec<TestSuite>, Error> {
+	let document = Document::parse(xml).map_err(|error| format_err!("Invalid XML. {}", error))?;
+
+	let mut suites = Vec::new();
+	parse_test_suites(&document.root_element(), &mut suites);
+	Ok(suites)
+}
+
+/// Find and parse all JUnit reports matching `patterns`. Relative patterns are resolved against
+/// `working_dir`. Reports which can't be read or parsed are logged and skipped.
+pub fn collect(patterns: &[String], working_dir: &str) -> Vec<TestSuite> {
+	let mut suites = Vec::new();
+
+	for pattern in patterns.iter() {
+		let pattern = if Path::new(pattern).is_absolute() {
+			pattern.to_owned()
+		} else {
+			format!("{}/{}", working_dir, pattern)
+		};
+
+		let paths = match glob(&pattern) {
+			Ok(paths) => paths,
+			Err(error) => {
+				error!("Invalid test report pattern {}. {}", &pattern, error);
+				continue;
+			}
+		};
+
+		for path in paths.filter_map(Result::ok) {
+			let result = read_to_string(&path)
+				.map_err(Error::from)
+				.and_then(|xml| parse(&xml));
+
+			match result {
+				Ok(mut report) => {
+					debug!("Parsed test report {}", path.display());
+					suites.append(&mut report);
+				}
+				Err(error) => error!("Unable to parse test report {}. {}", path.display(), error),
+			}
+		}
+	}
+
+	suites
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_reads_cases_and_their_status() {
+		let suites = parse(
+			r#"<?xml version="1.0" encoding="UTF-8"?>
+			<testsuite name="api" time="1,234.5">
+				<testcase name="creates users" classname="users" time="0.25"/>
+				<testcase name="deletes users" classname="users">
+					<failure message="expected 204">
+						assertion failed
+					</failure>
+				</testcase>
+				<testcase name="connects">
+					<error message="timed out"/>
+				</testcase>
+				<testcase name="migrates">
+					<skipped/>
+				</testcase>
+				<system-out>ignored</system-out>
+			</testsuite>"#,
+		)
+		.unwrap();
+
+		assert_eq!(suites.len(), 1);
+		let suite = &suites[0];
+		assert_eq!(suite.name, "api");
+		assert_eq!(suite.duration, 1234.5);
+		assert_eq!(suite.cases.len(), 4);
+		assert_eq!(suite.count(TestStatus::Passed), 1);
+		assert_eq!(suite.count(TestStatus::Failed), 1);
+		assert_eq!(suite.count(TestStatus::Errored), 1);
+		assert_eq!(suite.count(TestStatus::Skipped), 1);
+
+		let passed = &suite.cases[0];
+		assert_eq!(passed.classname.as_ref().map(String::as_str), Some("users"));
+		assert_eq!(passed.duration, 0.25);
+		assert_eq!(passed.message, None);
+
+		let failed = &suite.cases[1];
+		assert_eq!(failed.status, TestStatus::Failed);
+		assert_eq!(
+			failed.message.as_ref().map(String::as_str),
+			Some("expected 204")
+		);
+		assert_eq!(
+			failed.details.as_ref().map(String::as_str),
+			Some("assertion failed")
+		);
+
+		let errored = &suite.cases[2];
+		assert_eq!(errored.duration, 0.0);
+		assert_eq!(errored.details, None);
+	}
+
+	#[test]
+	fn parse_reads_nested_suites() {
+		let suites = parse(
+			r#"<testsuites>
+				<testsuite name="empty"/>
+				<testsuite name="outer">
+					<testcase name="a"/>
+					<testsuite name="inner">
+						<testcase name="b"/>
+					</testsuite>
+				</testsuite>
+			</testsuites>"#,
+		)
+		.unwrap();
+
+		let names: Vec<&str> = suites.iter().map(|suite| suite.name.as_str()).collect();
+		assert_eq!(names, vec!["outer", "inner"]);
+	}
+
+	#[test]
+	fn parse_rejects_invalid_xml() {
+		assert!(parse("<testsuite></testcase>").is_err());
+		assert!(parse("<results/>").unwrap().is_empty());
+	}
+}
@@ -3,20 +3,32 @@ use failure::{format_err, Error};
 use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 use std::{thread, time};
 
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
 
 use crate::config::AppConfig;
+use crate::model::builds::Builds;
 use crate::model::queues::Queues;
-use crate::model::repositories::Repositories;
+use crate::model::repositories::{Repositories, Repository};
 use crate::util::serialize_date;
 use crate::DbConnectionManager;
 
+pub mod cache;
+pub mod inbound;
 mod job;
+pub mod junit;
+pub mod poller;
+pub mod preprocess;
+pub mod scheduler;
+pub mod status;
+pub mod steps;
+pub mod triggers;
 use job::{CommandRunner, JobRunner};
+use status::StatusReporter;
+use steps::StepState;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "status", content = "exit_code")]
@@ -29,6 +41,10 @@ pub enum ExecutionStatus {
 	/// Queued for execution
 	Queued,
 
+	#[serde(rename = "awaiting_approval")]
+	/// Waiting for a user to approve or reject the execution
+	AwaitingApproval,
+
 	#[serde(rename = "running")]
 	/// Execution is currently in progress
 	Running,
@@ -85,6 +101,24 @@ pub struct QueueItem {
 	/// environment when the command is executed.
 	pub data: ArbitraryData,
 
+	/// Key/value pairs written by the command to the file at `LITTLECI_OUTPUT`.
+	pub outputs: HashMap<String, String>,
+
+	/// Build which this job belongs to if the repository has a build matrix.
+	pub build_id: Option<String>,
+
+	/// Matrix values for this job. They're injected into the `Command` environment.
+	pub matrix: HashMap<String, String>,
+
+	/// Job of an upstream repository which triggered this job.
+	pub upstream_job_id: Option<String>,
+
+	/// Dependency graph of the repository's steps and the status of each step.
+	pub steps: Vec<StepState>,
+
+	/// Identifies repeated deliveries of the notification which queued this job.
+	pub idempotency_key: Option<String>,
+
 	///
 	#[serde(serialize_with = "serialize_date")]
 	pub created_at: NaiveDateTime,
@@ -103,6 +137,12 @@ impl QueueItem {
 			repository_id: repository_id.to_owned(),
 			status: ExecutionStatus::Queued,
 			data,
+			outputs: HashMap::new(),
+			build_id: None,
+			matrix: HashMap::new(),
+			upstream_job_id: None,
+			steps: Vec::new(),
+			idempotency_key: None,
 			created_at: Utc::now().naive_utc(),
 			updated_at: Utc::now().naive_utc(),
 			logs: Vec::new(),
@@ -110,26 +150,111 @@ impl QueueItem {
 	}
 }
 
+/// A group of jobs created by a single notification for a repository with a build matrix.
+#[derive(Serialize, Debug, Clone)]
+pub struct Build {
+	pub id: String,
+
+	pub repository_id: String,
+
+	/// Combined status of all of the build's jobs
+	#[serde(flatten)]
+	pub status: ExecutionStatus,
+
+	#[serde(serialize_with = "serialize_date")]
+	pub created_at: NaiveDateTime,
+
+	pub jobs: Vec<QueueItem>,
+}
+
+impl Build {
+	fn new(repository_id: &str) -> Self {
+		Self {
+			id: nanoid::custom(24, &crate::ALPHA_NUMERIC),
+			repository_id: repository_id.to_owned(),
+			status: ExecutionStatus::Queued,
+			created_at: Utc::now().naive_utc(),
+			jobs: Vec::new(),
+		}
+	}
+
+	/// The build is running while any job is running, then awaiting approval or queued while any
+	/// job is. Once
+	/// all jobs have finished the build takes on the status of the first failed or cancelled job,
+	/// otherwise it's completed.
+	pub fn combined_status(jobs: &[QueueItem]) -> ExecutionStatus {
+		let mut running = false;
+		let mut queued = false;
+		let mut awaiting_approval = false;
+		let mut finished: Option<ExecutionStatus> = None;
+		let mut unknown = false;
+
+		for job in jobs.iter() {
+			match &job.status {
+				ExecutionStatus::Running => running = true,
+				ExecutionStatus::Queued => queued = true,
+				ExecutionStatus::AwaitingApproval => awaiting_approval = true,
+				ExecutionStatus::Failed(_) | ExecutionStatus::Cancelled => {
+					if finished.is_none() {
+						finished = Some(job.status.clone());
+					}
+				}
+				ExecutionStatus::Unknown => unknown = true,
+				_ => {}
+			}
+		}
+
+		if running {
+			ExecutionStatus::Running
+		} else if awaiting_approval {
+			ExecutionStatus::AwaitingApproval
+		} else if queued {
+			ExecutionStatus::Queued
+		} else if let Some(status) = finished {
+			status
+		} else if unknown {
+			ExecutionStatus::Unknown
+		} else {
+			ExecutionStatus::Completed
+		}
+	}
+}
+
 #[derive(Serialize, Debug, Clone)]
 pub struct QueueLogItem {
 	#[serde(flatten)]
 	pub status: ExecutionStatus,
 
+	/// User who changed the status, eg. by approving or rejecting the execution
+	pub username: Option<String>,
+
 	#[serde(serialize_with = "serialize_date")]
 	pub created_at: NaiveDateTime,
 }
 
+/// How often to check for jobs whose approval has timed out
+const APPROVAL_TIMEOUT_INTERVAL: time::Duration = time::Duration::from_secs(30);
+
+/// How often to check for scheduled jobs which are due
+const SCHEDULER_INTERVAL: time::Duration = time::Duration::from_secs(30);
+
+/// How often to check for git remotes which are due to be polled
+const POLLER_INTERVAL: time::Duration = time::Duration::from_secs(10);
+
 #[derive(Debug, Clone)]
 pub struct QueueManager {
 	pub config: Arc<AppConfig>,
 	pub connection_manager: DbConnectionManager,
 	pub model: Arc<Queues>,
 	pub queues: Arc<RwLock<HashMap<String, QueueService>>>,
+	pub reporter: StatusReporter,
 }
 
 impl QueueManager {
 	pub fn new(connection_manager: DbConnectionManager, config: Arc<AppConfig>) -> Self {
-		let mut queues = HashMap::new();
+		let queues: Arc<RwLock<HashMap<String, QueueService>>> =
+			Arc::new(RwLock::new(HashMap::new()));
+		let reporter = StatusReporter::new();
 
 		// Load all repositories to restart any jobs which were waiting in the queue.
 		let repositories_model = Repositories::new(connection_manager.clone());
@@ -138,16 +263,79 @@ impl QueueManager {
 				connection_manager.clone(),
 				config.clone(),
 				Arc::new(r.id.clone()),
+				Arc::downgrade(&queues),
+				reporter.clone(),
 			);
+			queues.write().insert(r.slug, queue.clone());
 			queue.notify();
-			queues.insert(r.slug, queue);
 		}
 
+		// Periodically reject jobs whose approval has timed out. The thread stops once the manager
+		// and its queues have been dropped.
+		let approval_queues = Arc::downgrade(&queues);
+		let approval_connection_manager = connection_manager.clone();
+		let approval_config = config.clone();
+		let approval_reporter = reporter.clone();
+		thread::spawn(move || loop {
+			match approval_queues.upgrade() {
+				Some(_) => expire_approvals(
+					&approval_connection_manager,
+					&approval_config,
+					&approval_reporter,
+				),
+				None => break,
+			}
+			thread::sleep(APPROVAL_TIMEOUT_INTERVAL);
+		});
+
+		// Periodically queue jobs for schedule triggers. The thread stops once the manager and
+		// its queues have been dropped.
+		let scheduler_queues = Arc::downgrade(&queues);
+		let scheduler_connection_manager = connection_manager.clone();
+		let scheduler_config = config.clone();
+		let scheduler_reporter = reporter.clone();
+		thread::spawn(move || loop {
+			match scheduler_queues.upgrade() {
+				Some(queues) => scheduler::run_due(&QueueManager {
+					config: scheduler_config.clone(),
+					connection_manager: scheduler_connection_manager.clone(),
+					model: Arc::new(Queues::new(scheduler_connection_manager.clone())),
+					queues,
+					reporter: scheduler_reporter.clone(),
+				}),
+				None => break,
+			}
+			thread::sleep(SCHEDULER_INTERVAL);
+		});
+
+		// Periodically poll the git remotes of poll triggers
+		let poller_queues = Arc::downgrade(&queues);
+		let poller_connection_manager = connection_manager.clone();
+		let poller_config = config.clone();
+		let poller_reporter = reporter.clone();
+		thread::spawn(move || {
+			let mut poller = poller::Poller::new();
+			loop {
+				match poller_queues.upgrade() {
+					Some(queues) => poller.run_due(&QueueManager {
+						config: poller_config.clone(),
+						connection_manager: poller_connection_manager.clone(),
+						model: Arc::new(Queues::new(poller_connection_manager.clone())),
+						queues,
+						reporter: poller_reporter.clone(),
+					}),
+					None => break,
+				}
+				thread::sleep(POLLER_INTERVAL);
+			}
+		});
+
 		Self {
 			connection_manager: connection_manager.clone(),
 			config,
 			model: Arc::new(Queues::new(connection_manager.clone())),
-			queues: Arc::new(RwLock::new(queues)),
+			queues,
+			reporter,
 		}
 	}
 
@@ -209,18 +397,42 @@ impl QueueManager {
 		}
 	}
 
-	pub fn push(&self, repository_slug: &str, data: ArbitraryData) -> Result<QueueItem, Error> {
+	/// Queue a job for the repository. If the repository has a build matrix, one job is queued
+	/// per combination and grouped into a build. The first job of the build is returned.
+	///
+	/// A key or value of the repository's `idempotency_variable` which queued a job within the
+	/// configured retention returns that job instead of queueing another.
+	pub fn push(
+		&self,
+		repository_slug: &str,
+		data: ArbitraryData,
+		idempotency_key: Option<String>,
+	) -> Result<QueueItem, Error> {
+		self.enqueue(repository_slug, data, None, idempotency_key)
+	}
+
+	/// Queue a job for a downstream repository, linking it to the upstream job which triggered it.
+	pub fn push_downstream(
+		&self,
+		repository_slug: &str,
+		data: ArbitraryData,
+		upstream_job: &QueueItem,
+	) -> Result<QueueItem, Error> {
+		self.enqueue(repository_slug, data, Some(&upstream_job.id), None)
+	}
+
+	/// Find the queue service for a repository, creating one if it doesn't exist yet.
+	fn service(&self, repository_slug: &str) -> Result<(QueueService, Repository), Error> {
 		let repositories_model = Repositories::new(self.connection_manager.clone());
 
-		let mut service_item: Option<(QueueService, QueueItem)> = None;
+		let mut service_repository: Option<(QueueService, Repository)> = None;
 		// First see if we the service already exists in the queues map.
 		{
 			let queues = self.queues.read();
 			if let Some(queue) = queues.get(repository_slug) {
 				if let Some(repository) = repositories_model.find_by_slug(&repository_slug) {
 					if !repository.deleted {
-						service_item =
-							Some((queue.clone(), QueueItem::new(&repository.id, data.clone())));
+						service_repository = Some((queue.clone(), repository));
 					} else {
 						error!(
 							"Repository {} has been marked as deleted. Not adding job to queue.",
@@ -235,21 +447,22 @@ impl QueueManager {
 		// If it doesn't, create a new service for the repository
 		// XXX This seems a bit tacky, but I couldn't think of another way of doing this without
 		// creating a read lock and blocking a write lock if we needed to create a new service.
-		if service_item.is_none() {
+		if service_repository.is_none() {
 			match repositories_model.find_by_slug(&repository_slug) {
 				Some(repository) => {
 					if !repository.deleted {
-						let repository_id = repository.id.clone();
 						let queue = QueueService::new(
 							self.connection_manager.clone(),
 							self.config.clone(),
 							Arc::new(repository.id.clone()),
+							Arc::downgrade(&self.queues),
+							self.reporter.clone(),
 						);
 
 						let mut queues = self.queues.write();
 						queues.insert(repository_slug.clone().into(), queue.clone());
 
-						service_item = Some((queue, QueueItem::new(&repository_id, data)));
+						service_repository = Some((queue, repository));
 					} else {
 						error!(
 							"Repository {} has been marked as deleted. Not creating queue.",
@@ -262,13 +475,212 @@ impl QueueManager {
 			}
 		}
 
-		// We shouldn't get to this point without service_item being `Some()`
-		let (queue, item) = service_item.expect("Unable to read from queue");
-		// Add the job to the database and notify the queue service that there's something to
+		// We shouldn't get to this point without service_repository being `Some()`
+		Ok(service_repository.expect("Unable to read from queue"))
+	}
+
+	fn enqueue(
+		&self,
+		repository_slug: &str,
+		data: ArbitraryData,
+		upstream_job_id: Option<&str>,
+		idempotency_key: Option<String>,
+	) -> Result<QueueItem, Error> {
+		let (queue, repository) = self.service(repository_slug)?;
+
+		// Repeated deliveries of a notification return the job queued by the first delivery
+		let idempotency_key = idempotency_key.or_else(|| {
+			let variable = repository.idempotency_variable.as_ref()?;
+			data.inner().get(variable).cloned()
+		});
+		if let Some(key) = &idempotency_key {
+			let retention = chrono::Duration::seconds(self.config.idempotency_retention as i64);
+			let since = Utc::now().naive_utc() - retention;
+			if let Some(job) = self.model.find_by_idempotency_key(&repository.id, key, since) {
+				info!(
+					"Job {} was already queued with idempotency key `{}`",
+					&job.id, key
+				);
+				return Ok(job);
+			}
+			self.model
+				.release_idempotency_key(&repository.id, key, since)?;
+		}
+
+		let combinations = match &repository.matrix {
+			Some(matrix) => matrix.expand(),
+			None => Vec::new(),
+		};
+
+		let mut items = if combinations.is_empty() {
+			vec![QueueItem::new(&repository.id, data)]
+		} else {
+			let build = Build::new(&repository.id);
+			Builds::new(self.connection_manager.clone()).create(&build)?;
+			info!(
+				"Expanding build {} into {} jobs",
+				&build.id,
+				combinations.len()
+			);
+
+			combinations
+				.into_iter()
+				.map(|combination| {
+					let mut item = QueueItem::new(&repository.id, data.clone());
+					item.build_id = Some(build.id.clone());
+					item.matrix = combination;
+					item
+				})
+				.collect()
+		};
+
+		// Only the job which is returned is looked up by its key
+		if let Some(item) = items.first_mut() {
+			item.idempotency_key = idempotency_key;
+		}
+
+		for item in items.iter_mut() {
+			item.upstream_job_id = upstream_job_id.map(String::from);
+			if repository.requires_approval {
+				item.status = ExecutionStatus::AwaitingApproval;
+			}
+		}
+
+		// A concurrent delivery may have taken the key since it was looked up
+		let first = items.first().expect("No jobs were created");
+		if first.idempotency_key.is_some() {
+			if let Some(job) = self.model.push_idempotent(first)? {
+				info!(
+					"Job {} was queued with the same idempotency key by a concurrent delivery",
+					&job.id
+				);
+				// None of this build's jobs were added
+				if let Some(build_id) = &first.build_id {
+					Builds::new(self.connection_manager.clone()).delete_by_id(build_id)?;
+				}
+				return Ok(job);
+			}
+		} else {
+			self.model.push(first);
+		}
+
+		// Add the jobs to the database and notify the queue service that there's something to
 		// process
-		self.model.push(&item);
+		for item in items.iter().skip(1) {
+			self.model.push(&item);
+		}
+		for item in items.iter() {
+			self.reporter.report(&self.config, &repository, item);
+		}
 		queue.notify();
-		Ok(item)
+		Ok(items.into_iter().next().expect("No jobs were created"))
+	}
+
+	/// Approve a job which is awaiting approval. Jobs which haven't started yet are queued, jobs
+	/// which are waiting for a step to be approved continue running.
+	pub fn approve(
+		&self,
+		repository_slug: &str,
+		job_id: &str,
+		user: Option<&str>,
+	) -> Result<QueueItem, Error> {
+		let (queue, repository) = self.service(repository_slug)?;
+		let mut job = self.model.job(&repository.id, job_id)?;
+
+		match job.status {
+			ExecutionStatus::AwaitingApproval => {}
+			_ => return Err(format_err!("Job {} is not awaiting approval", job_id)),
+		}
+
+		let started = job.logs.iter().any(|log| match log.status {
+			ExecutionStatus::Running => true,
+			_ => false,
+		});
+
+		job.status = if started {
+			ExecutionStatus::Running
+		} else {
+			ExecutionStatus::Queued
+		};
+		self.model.update_status_by(&job, user)?;
+		self.reporter.report(&self.config, &repository, &job);
+		info!("Job {} approved by {}", job_id, user.unwrap_or("anonymous"));
+
+		if !started {
+			queue.notify();
+		}
+		Ok(job)
+	}
+
+	/// Reject a job which is awaiting approval, cancelling it.
+	pub fn reject(
+		&self,
+		repository_slug: &str,
+		job_id: &str,
+		user: Option<&str>,
+	) -> Result<QueueItem, Error> {
+		let (_, repository) = self.service(repository_slug)?;
+		let mut job = self.model.job(&repository.id, job_id)?;
+
+		match job.status {
+			ExecutionStatus::AwaitingApproval => {}
+			_ => return Err(format_err!("Job {} is not awaiting approval", job_id)),
+		}
+
+		job.status = ExecutionStatus::Cancelled;
+		self.model.update_status_by(&job, user)?;
+		self.reporter.report(&self.config, &repository, &job);
+		info!("Job {} rejected by {}", job_id, user.unwrap_or("anonymous"));
+		Ok(job)
+	}
+}
+
+/// Cancel jobs which have been awaiting approval for longer than their repository's approval
+/// timeout.
+fn expire_approvals(
+	connection_manager: &DbConnectionManager,
+	config: &AppConfig,
+	reporter: &StatusReporter,
+) {
+	let queue_model = Queues::new(connection_manager.clone());
+	let repositories_model = Repositories::new(connection_manager.clone());
+
+	let jobs = match queue_model.awaiting_approval() {
+		Ok(jobs) => jobs,
+		Err(error) => {
+			error!("{}", error);
+			return;
+		}
+	};
+
+	for mut job in jobs.into_iter() {
+		let repository = match repositories_model.find_by_id(&job.repository_id) {
+			Some(repository) => repository,
+			None => continue,
+		};
+
+		if let Some(timeout) = repository.approval_timeout {
+			// Running steps keep updating the job, so the wait starts when approval was requested
+			let waiting_since = job
+				.logs
+				.iter()
+				.filter(|log| match log.status {
+					ExecutionStatus::AwaitingApproval => true,
+					_ => false,
+				})
+				.map(|log| log.created_at)
+				.max()
+				.unwrap_or(job.updated_at);
+			let expires_at = waiting_since + chrono::Duration::seconds(timeout.into());
+			if expires_at < Utc::now().naive_utc() {
+				info!("Approval of job {} timed out. Rejecting.", &job.id);
+				job.status = ExecutionStatus::Cancelled;
+				if let Err(error) = queue_model.update_status(&job) {
+					error!("Unable to update status of job {}. {}", &job.id, error);
+				}
+				reporter.report(config, &repository, &job);
+			}
+		}
 	}
 }
 
@@ -289,6 +701,10 @@ pub struct QueueService {
 	pub processing_queue: Arc<Mutex<ProcessingQueue>>,
 	pub runner: Arc<dyn JobRunner>,
 	pub service_state: Arc<Mutex<ServiceState>>,
+	/// Services of all repositories, shared with the `QueueManager` so that finished jobs can
+	/// trigger downstream repositories. Weak so that services don't keep each other alive.
+	pub queues: Weak<RwLock<HashMap<String, QueueService>>>,
+	pub reporter: StatusReporter,
 }
 
 impl QueueService {
@@ -296,6 +712,8 @@ impl QueueService {
 		connection_manager: DbConnectionManager,
 		config: Arc<AppConfig>,
 		repository_id: Arc<String>,
+		queues: Weak<RwLock<HashMap<String, QueueService>>>,
+		reporter: StatusReporter,
 	) -> Self {
 		Self {
 			config,
@@ -304,9 +722,22 @@ impl QueueService {
 			processing_queue: Arc::new(Mutex::new(ProcessingQueue)),
 			runner: Arc::new(CommandRunner),
 			service_state: Arc::new(Mutex::new(ServiceState::Active)),
+			queues,
+			reporter,
 		}
 	}
 
+	/// A `QueueManager` sharing this service's queues, unless the manager has been dropped.
+	fn manager(&self) -> Option<QueueManager> {
+		self.queues.upgrade().map(|queues| QueueManager {
+			config: self.config.clone(),
+			connection_manager: self.connection_manager.clone(),
+			model: Arc::new(Queues::new(self.connection_manager.clone())),
+			queues,
+			reporter: self.reporter.clone(),
+		})
+	}
+
 	fn notify(&self) {
 		self.runner.process(self.clone());
 	}
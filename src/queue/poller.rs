@@ -0,0 +1,237 @@
+use failure::{format_err, Error};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::str;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use super::triggers::{matches_patterns, PushType};
+use super::{ArbitraryData, QueueManager};
+use crate::config::Trigger;
+use crate::model::polled_refs::PolledRefs;
+use crate::model::repositories::{Repositories, Repository};
+
+/// Passed as `LITTLECI_GIT_BEFORE` for branches which didn't exist at the previous poll
+const NULL_SHA: &str = "0000000000000000000000000000000000000000";
+
+/// Check that the URLs and intervals of all poll triggers are valid.
+pub fn validate(triggers: &[Trigger]) -> Result<(), Error> {
+	for trigger in triggers.iter() {
+		if let Trigger::Poll { url, interval, .. } = trigger {
+			if url.trim().is_empty() {
+				return Err(format_err!("Poll URL is missing"));
+			}
+			if *interval == 0 {
+				return Err(format_err!(
+					"Poll interval of `{}` must be at least 1 second",
+					url
+				));
+			}
+		}
+	}
+	Ok(())
+}
+
+/// How long `git ls-remote` may take before it's killed, eg. when a remote stops responding
+const LS_REMOTE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Read everything from a pipe of a child process in the background.
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+	thread::spawn(move || {
+		let mut buffer = Vec::new();
+		if let Some(mut pipe) = pipe {
+			let _ = pipe.read_to_end(&mut buffer);
+		}
+		buffer
+	})
+}
+
+/// The branches of a remote and the commits they point to, from `git ls-remote`.
+fn ls_remote(url: &str) -> Result<BTreeMap<String, String>, Error> {
+	let mut child = Command::new("git")
+		// `--` stops URLs starting with a dash being read as options
+		.args(&["ls-remote", "--heads", "--", url])
+		// Fail instead of waiting for credentials which will never be entered
+		.env("GIT_TERMINAL_PROMPT", "0")
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.map_err(|error| format_err!("Unable to run git ls-remote. {}", error))?;
+
+	// Drain the pipes while waiting so a large listing can't fill them and block git
+	let stdout = read_pipe(child.stdout.take());
+	let stderr = read_pipe(child.stderr.take());
+
+	let deadline = Instant::now() + LS_REMOTE_TIMEOUT;
+	let status = loop {
+		match child.try_wait() {
+			Ok(Some(status)) => break status,
+			Ok(None) if Instant::now() >= deadline => {
+				let _ = child.kill();
+				let _ = child.wait();
+				return Err(format_err!(
+					"git ls-remote timed out after {} seconds",
+					LS_REMOTE_TIMEOUT.as_secs()
+				));
+			}
+			Ok(None) => thread::sleep(Duration::from_millis(100)),
+			Err(error) => {
+				let _ = child.kill();
+				return Err(format_err!("Unable to wait for git ls-remote. {}", error));
+			}
+		}
+	};
+
+	let stdout = stdout.join().unwrap_or_default();
+	let stderr = stderr.join().unwrap_or_default();
+
+	if !status.success() {
+		return Err(format_err!(
+			"git ls-remote failed. {}",
+			String::from_utf8_lossy(&stderr).trim()
+		));
+	}
+
+	let stdout = str::from_utf8(&stdout)
+		.map_err(|error| format_err!("Invalid git ls-remote output. {}", error))?;
+
+	Ok(stdout
+		.lines()
+		.filter_map(|line| {
+			let mut parts = line.split('\t');
+			let sha = parts.next()?;
+			let branch = parts.next()?.trim_start_matches("refs/heads/");
+			Some((branch.to_owned(), sha.to_owned()))
+		})
+		.collect())
+}
+
+/// Polls the remotes of poll triggers, queueing a job for every watched branch which moved.
+#[derive(Debug, Default)]
+pub struct Poller {
+	/// When each remote was last polled, by repository and URL
+	last_polled: HashMap<String, Instant>,
+	/// Remotes polled successfully since LittleCI started
+	polled: HashSet<String>,
+}
+
+impl Poller {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Poll any remotes whose interval has passed.
+	pub fn run_due(&mut self, queue_manager: &QueueManager) {
+		let repositories = Repositories::new(queue_manager.connection_manager.clone()).all();
+
+		for repository in repositories.iter() {
+			for trigger in repository.triggers.iter() {
+				let (url, refs, interval) = match trigger {
+					Trigger::Poll {
+						url,
+						refs,
+						interval,
+					} => (url, refs, interval),
+					_ => continue,
+				};
+
+				let key = format!("{} {}", &repository.id, url);
+				if let Some(last_polled) = self.last_polled.get(&key) {
+					if last_polled.elapsed() < Duration::from_secs(*interval) {
+						continue;
+					}
+				}
+				self.last_polled.insert(key.clone(), Instant::now());
+
+				let first_poll = !self.polled.contains(&key);
+				match poll(queue_manager, repository, url, refs, first_poll) {
+					Ok(()) => {
+						self.polled.insert(key);
+					}
+					Err(error) => warn!(
+						"Unable to poll `{}` for repository {}. {}",
+						url, &repository.slug, error
+					),
+				}
+			}
+		}
+	}
+}
+
+/// Compare the branches of a remote with those seen at the previous poll. Remotes which have
+/// never been polled only record their branches, so adding a trigger doesn't queue a job for
+/// every existing branch.
+fn poll(
+	queue_manager: &QueueManager,
+	repository: &Repository,
+	url: &str,
+	refs: &[String],
+	first_poll: bool,
+) -> Result<(), Error> {
+	let remote = ls_remote(url)?;
+	let polled_refs = PolledRefs::new(queue_manager.connection_manager.clone());
+	let seen = polled_refs.for_url(&repository.id, url);
+	let baseline = first_poll && seen.is_empty();
+
+	let mut watched = HashSet::new();
+	for (branch, sha) in remote.iter() {
+		if !matches_patterns(refs, branch)? {
+			continue;
+		}
+		watched.insert(branch);
+
+		let before = seen.get(branch);
+		if before == Some(sha) {
+			continue;
+		}
+
+		// Record the move first so that a failure to queue the job doesn't repeat it on every
+		// poll
+		polled_refs.record(&repository.id, url, branch, sha)?;
+
+		if baseline {
+			continue;
+		}
+
+		let push_type = match before {
+			Some(_) => PushType::Push,
+			None => PushType::Create,
+		};
+
+		let mut data: HashMap<String, String> = HashMap::new();
+		data.insert("LITTLECI_GIT_EVENT".into(), push_type.as_str().into());
+		data.insert("LITTLECI_GIT_BRANCH".into(), branch.clone());
+		data.insert(
+			"LITTLECI_GIT_BEFORE".into(),
+			before.map(String::as_str).unwrap_or(NULL_SHA).into(),
+		);
+		data.insert("LITTLECI_GIT_AFTER".into(), sha.clone());
+
+		match queue_manager.push(&repository.slug, ArbitraryData::new(data), None) {
+			Ok(job) => info!(
+				"Polling `{}` queued job {} for {} of {} in repository {}",
+				url,
+				&job.id,
+				push_type.as_str(),
+				branch,
+				&repository.slug
+			),
+			Err(error) => error!(
+				"Unable to queue polled job for repository {}. {}",
+				&repository.slug, error
+			),
+		}
+	}
+
+	// Forget branches which were deleted or are no longer watched
+	for branch in seen.keys().filter(|branch| !watched.contains(branch)) {
+		polled_refs.remove(&repository.id, url, branch)?;
+	}
+
+	Ok(())
+}
@@ -0,0 +1,57 @@
+use failure::{format_err, Error};
+use regex::Regex;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use super::QueueItem;
+use crate::config::SkipRule;
+use crate::model::repositories::Repository;
+
+/// Markers which skip a job when they appear in the head commit message.
+const SKIP_MARKERS: [&str; 2] = ["[skip ci]", "[ci skip]"];
+
+/// Check that the patterns of all skip rules are valid regular expressions.
+pub fn validate(rules: &[SkipRule]) -> Result<(), Error> {
+	for rule in rules.iter() {
+		if let Err(error) = Regex::new(&rule.pattern) {
+			return Err(format_err!("Invalid pattern `{}`. {}", rule.pattern, error));
+		}
+	}
+	Ok(())
+}
+
+/// The reason a job should be skipped, if any of the skip rules match the job's data.
+pub fn skip_reason(repository: &Repository, item: &QueueItem) -> Option<String> {
+	let data = item.data.inner();
+
+	if let Some(message) = data.get("LITTLECI_GIT_COMMIT_MESSAGE") {
+		let message = message.to_lowercase();
+		if let Some(marker) = SKIP_MARKERS.iter().find(|m| message.contains(*m)) {
+			return Some(format!("Commit message contains `{}`", marker));
+		}
+	}
+
+	for rule in repository.skip_rules.iter() {
+		let value = match data.get(&rule.variable) {
+			Some(value) => value,
+			None => continue,
+		};
+
+		match Regex::new(&rule.pattern) {
+			Ok(pattern) => {
+				if pattern.is_match(value) {
+					return Some(rule.reason.clone().unwrap_or_else(|| {
+						format!("`{}` matched `{}`", &rule.variable, &rule.pattern)
+					}));
+				}
+			}
+			Err(error) => error!(
+				"Invalid skip rule pattern `{}` for repository {}. {}",
+				&rule.pattern, &repository.slug, error
+			),
+		}
+	}
+
+	None
+}
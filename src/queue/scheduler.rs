@@ -0,0 +1,170 @@
+use chrono::{Duration, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use failure::{format_err, Error};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use super::{ArbitraryData, QueueManager};
+use crate::config::{MissedRuns, Trigger};
+use crate::model::repositories::Repositories;
+use crate::model::schedule_runs::ScheduleRuns;
+use crate::util::utc_now;
+
+/// Runs which were due longer ago than this were missed, eg. because LittleCI wasn't running.
+const MISSED_RUN_THRESHOLD: i64 = 120;
+
+/// Parse a cron expression and timezone. Expressions may have 5 fields, like crontab, or include
+/// seconds and years as supported by the `cron` crate.
+fn parse(cron: &str, timezone: &Option<String>) -> Result<(Schedule, Tz), Error> {
+	let expression = if cron.split_whitespace().count() == 5 {
+		format!("0 {}", cron)
+	} else {
+		cron.to_owned()
+	};
+
+	let schedule = Schedule::from_str(&expression)
+		.map_err(|error| format_err!("Invalid cron expression `{}`. {}", cron, error))?;
+
+	let timezone = match timezone {
+		Some(timezone) => timezone
+			.parse::<Tz>()
+			.map_err(|error| format_err!("Invalid timezone `{}`. {}", timezone, error))?,
+		None => Tz::UTC,
+	};
+
+	Ok((schedule, timezone))
+}
+
+/// The first time the schedule is due after `after`, both in UTC.
+fn next_run(
+	cron: &str,
+	timezone: &Option<String>,
+	after: NaiveDateTime,
+) -> Result<Option<NaiveDateTime>, Error> {
+	let (schedule, timezone) = parse(cron, timezone)?;
+	let after = timezone.from_utc_datetime(&after);
+
+	Ok(schedule
+		.after(&after)
+		.next()
+		.map(|next| next.with_timezone(&Utc).naive_utc()))
+}
+
+/// Identifies a schedule when recording when it last ran. Schedules which only differ by their
+/// data are told apart by a hash of the data.
+fn schedule_key(cron: &str, timezone: &Option<String>, data: &HashMap<String, String>) -> String {
+	let timezone = timezone.as_ref().map(String::as_str).unwrap_or("UTC");
+	let key = format!("{} {}", cron, timezone);
+	if data.is_empty() {
+		return key;
+	}
+
+	// Sorted so that the hash doesn't depend on the order of the keys
+	let data: BTreeMap<&String, &String> = data.iter().collect();
+	let data = serde_json::to_string(&data).expect("Unable to serialize schedule data to JSON");
+	let hash = hex::encode(Sha256::digest(data.as_bytes()));
+	format!("{} {}", key, &hash[..16])
+}
+
+/// Check that the cron expressions and timezones of all schedule triggers are valid.
+pub fn validate(triggers: &[Trigger]) -> Result<(), Error> {
+	for trigger in triggers.iter() {
+		if let Trigger::Schedule { cron, timezone, .. } = trigger {
+			parse(cron, timezone)?;
+		}
+	}
+	Ok(())
+}
+
+/// The next time any of the schedule triggers are due.
+pub fn next_scheduled_run(triggers: &[Trigger]) -> Option<NaiveDateTime> {
+	let now = utc_now();
+
+	triggers
+		.iter()
+		.filter_map(|trigger| match trigger {
+			Trigger::Schedule { cron, timezone, .. } => next_run(cron, timezone, now).ok()?,
+			_ => None,
+		})
+		.min()
+}
+
+/// Queue jobs for any schedules which are due. Schedules which haven't run before start counting
+/// from now, so adding a schedule doesn't immediately queue a job.
+pub fn run_due(queue_manager: &QueueManager) {
+	let repositories = Repositories::new(queue_manager.connection_manager.clone()).all();
+	let schedule_runs = ScheduleRuns::new(queue_manager.connection_manager.clone());
+	let now = utc_now();
+
+	for repository in repositories.iter() {
+		for trigger in repository.triggers.iter() {
+			let (cron, timezone, data, missed) = match trigger {
+				Trigger::Schedule {
+					cron,
+					timezone,
+					data,
+					missed,
+				} => (cron, timezone, data, missed),
+				_ => continue,
+			};
+
+			let key = schedule_key(cron, timezone, data);
+			let last_run = match schedule_runs.last_run(&repository.id, &key) {
+				Some(last_run) => last_run,
+				None => {
+					if let Err(error) = schedule_runs.record(&repository.id, &key, now) {
+						error!("{}", error);
+					}
+					continue;
+				}
+			};
+
+			let next = match next_run(cron, timezone, last_run) {
+				Ok(Some(next)) => next,
+				Ok(None) => continue,
+				Err(error) => {
+					error!("Schedule for repository {} is invalid. {}", &repository.slug, error);
+					continue;
+				}
+			};
+
+			if next > now {
+				continue;
+			}
+
+			// Record the run first so that a failure to queue the job doesn't repeat it on every
+			// check. This also means several missed runs only result in a single job.
+			if let Err(error) = schedule_runs.record(&repository.id, &key, now) {
+				error!("{}", error);
+				continue;
+			}
+
+			if now - next > Duration::seconds(MISSED_RUN_THRESHOLD) && *missed == MissedRuns::Skip {
+				info!(
+					"Skipping missed run of schedule `{}` for repository {}",
+					&key, &repository.slug
+				);
+				continue;
+			}
+
+			let mut data = data.clone();
+			data.insert("LITTLECI_SCHEDULE".into(), cron.clone());
+
+			match queue_manager.push(&repository.slug, ArbitraryData::new(data), None) {
+				Ok(job) => info!(
+					"Schedule `{}` queued job {} for repository {}",
+					&key, &job.id, &repository.slug
+				),
+				Err(error) => error!(
+					"Unable to queue scheduled job for repository {}. {}",
+					&repository.slug, error
+				),
+			}
+		}
+	}
+}
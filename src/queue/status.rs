@@ -0,0 +1,334 @@
+use failure::{format_err, Error};
+use parking_lot::Mutex;
+use reqwest::Client;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use super::{ExecutionStatus, QueueItem};
+use crate::config::{AppConfig, StatusProvider, StatusReporting};
+use crate::model::repositories::Repository;
+
+/// Attempts at reporting a status before giving up
+const ATTEMPTS: u32 = 3;
+
+/// Delay before retrying a failed report, doubled after each attempt
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Check that the API of the provider can be found.
+pub fn validate(settings: &StatusReporting) -> Result<(), Error> {
+	if settings.repository.is_empty() {
+		return Err(format_err!("Repository is missing"));
+	}
+	if settings.token.is_empty() {
+		return Err(format_err!("Token is missing"));
+	}
+	if settings.provider == StatusProvider::Gitea && settings.base_url.is_none() {
+		return Err(format_err!("Base URL is required for Gitea"));
+	}
+	Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CommitState {
+	Pending,
+	Running,
+	Success,
+	Failure,
+	Skipped,
+}
+
+impl CommitState {
+	fn from_status(status: &ExecutionStatus) -> Option<Self> {
+		match status {
+			ExecutionStatus::Queued | ExecutionStatus::AwaitingApproval => {
+				Some(CommitState::Pending)
+			}
+			ExecutionStatus::Running => Some(CommitState::Running),
+			ExecutionStatus::Completed => Some(CommitState::Success),
+			ExecutionStatus::Skipped(_) => Some(CommitState::Skipped),
+			ExecutionStatus::Failed(_) | ExecutionStatus::Cancelled => Some(CommitState::Failure),
+			ExecutionStatus::Unknown => None,
+		}
+	}
+
+	/// The state's name in the provider's API. Only GitLab distinguishes running commits.
+	/// Skipped jobs were reported as pending when they were queued, so they always get a final
+	/// state. GitHub has no neutral state, so they're reported as successful there, with the
+	/// reason in the description.
+	fn as_str(&self, provider: StatusProvider) -> &'static str {
+		match (self, provider) {
+			(CommitState::Pending, _) => "pending",
+			(CommitState::Running, StatusProvider::GitLab) => "running",
+			(CommitState::Running, _) => "pending",
+			(CommitState::Success, _) => "success",
+			(CommitState::Failure, StatusProvider::GitLab) => "failed",
+			(CommitState::Failure, _) => "failure",
+			(CommitState::Skipped, StatusProvider::GitLab) => "skipped",
+			(CommitState::Skipped, StatusProvider::Gitea) => "warning",
+			(CommitState::Skipped, StatusProvider::GitHub) => "success",
+		}
+	}
+}
+
+/// Name of the status on the commit. Jobs of a matrix get a status per combination, so they
+/// don't overwrite each other's.
+fn context(settings: &StatusReporting, matrix: &HashMap<String, String>) -> String {
+	if matrix.is_empty() {
+		return settings.context.clone();
+	}
+
+	let combination: BTreeMap<&String, &String> = matrix.iter().collect();
+	let combination: Vec<String> = combination
+		.into_iter()
+		.map(|(name, value)| format!("{}={}", name, value))
+		.collect();
+	format!("{} ({})", &settings.context, combination.join(", "))
+}
+
+fn description(status: &ExecutionStatus) -> String {
+	match status {
+		ExecutionStatus::Queued => "Queued".into(),
+		ExecutionStatus::AwaitingApproval => "Awaiting approval".into(),
+		ExecutionStatus::Running => "Running".into(),
+		ExecutionStatus::Completed => "Completed".into(),
+		ExecutionStatus::Failed(exit_code) => format!("Failed with exit code {}", exit_code),
+		ExecutionStatus::Cancelled => "Cancelled".into(),
+		ExecutionStatus::Skipped(reason) => format!("Skipped. {}", reason),
+		ExecutionStatus::Unknown => "Unknown".into(),
+	}
+}
+
+/// The status of a job, to be set on the commit it built.
+#[derive(Debug, Clone)]
+struct StatusReport {
+	settings: StatusReporting,
+	job_id: String,
+	sha: String,
+	/// Name of the state in the provider's API
+	state: &'static str,
+	context: String,
+	description: String,
+	target_url: String,
+}
+
+impl StatusReport {
+	fn url(&self) -> String {
+		let settings = &self.settings;
+		let base_url = match (&settings.base_url, settings.provider) {
+			(Some(base_url), _) => base_url.trim_end_matches('/').to_owned(),
+			(None, StatusProvider::GitLab) => "https://gitlab.com/api/v4".into(),
+			(None, _) => "https://api.github.com".into(),
+		};
+
+		match settings.provider {
+			StatusProvider::GitHub | StatusProvider::Gitea => format!(
+				"{}/repos/{}/statuses/{}",
+				base_url, &settings.repository, &self.sha
+			),
+			// Project paths are passed as a single, encoded path segment
+			StatusProvider::GitLab => format!(
+				"{}/projects/{}/statuses/{}",
+				base_url,
+				settings.repository.replace('/', "%2F"),
+				&self.sha
+			),
+		}
+	}
+
+	fn send(&self, client: &Client) -> Result<(), Error> {
+		let settings = &self.settings;
+		let state = self.state;
+		let request = client.post(&self.url());
+
+		let request = match settings.provider {
+			StatusProvider::GitHub | StatusProvider::Gitea => request
+				.header("Authorization", format!("token {}", &settings.token))
+				.json(&json!({
+					"state": state,
+					"target_url": &self.target_url,
+					"description": &self.description,
+					"context": &self.context,
+				})),
+			StatusProvider::GitLab => request
+				.header("PRIVATE-TOKEN", settings.token.as_str())
+				.json(&json!({
+					"state": state,
+					"target_url": &self.target_url,
+					"description": &self.description,
+					"name": &self.context,
+				})),
+		};
+
+		let response = request.header("User-Agent", "LittleCI").send()?;
+		if response.status().is_success() {
+			Ok(())
+		} else {
+			Err(format_err!("Responded with {}", response.status()))
+		}
+	}
+}
+
+/// Sends reports one at a time, in the order jobs changed status, so a retried report can't
+/// overwrite a newer status.
+fn report_all(reports: impl Iterator<Item = StatusReport>) {
+	let client = match Client::builder().timeout(REQUEST_TIMEOUT).build() {
+		Ok(client) => client,
+		Err(error) => {
+			error!("Unable to create status reporting client. {}", error);
+			return;
+		}
+	};
+
+	for report in reports {
+		let mut delay = RETRY_DELAY;
+		for attempt in 1..=ATTEMPTS {
+			match report.send(&client) {
+				Ok(()) => {
+					debug!(
+						"Reported {} status of job {} for commit {}",
+						report.state, &report.job_id, &report.sha
+					);
+					break;
+				}
+				Err(error) => {
+					warn!(
+						"Unable to report status of job {} to {} (attempt {} of {}). {}",
+						&report.job_id,
+						report.url(),
+						attempt,
+						ATTEMPTS,
+						error
+					);
+					if attempt < ATTEMPTS {
+						thread::sleep(delay);
+						delay *= 2;
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Reports job statuses to git forges in the background, so a slow or failing API doesn't
+/// affect builds.
+#[derive(Debug, Clone)]
+pub struct StatusReporter {
+	sender: Arc<Mutex<Sender<StatusReport>>>,
+}
+
+impl StatusReporter {
+	pub fn new() -> Self {
+		let (sender, receiver) = channel();
+		// The thread stops once every reporter has been dropped
+		thread::spawn(move || report_all(receiver.into_iter()));
+
+		Self {
+			sender: Arc::new(Mutex::new(sender)),
+		}
+	}
+
+	/// Report the job's current status, if the repository reports statuses and the job was
+	/// queued for a commit.
+	pub fn report(&self, config: &AppConfig, repository: &Repository, item: &QueueItem) {
+		let settings = match &repository.status_reporting {
+			Some(settings) => settings,
+			None => return,
+		};
+
+		let sha = match item.data.inner().get("LITTLECI_GIT_AFTER") {
+			Some(sha) => sha,
+			None => return,
+		};
+
+		let state = match CommitState::from_status(&item.status) {
+			Some(state) => state.as_str(settings.provider),
+			None => return,
+		};
+
+		let report = StatusReport {
+			settings: settings.clone(),
+			job_id: item.id.clone(),
+			sha: sha.clone(),
+			state,
+			context: context(settings, &item.matrix),
+			description: description(&item.status),
+			// The job's page in the UI
+			target_url: format!(
+				"{}/ui/repositories/{}/jobs/{}",
+				config.public_url(),
+				&repository.slug,
+				&item.id
+			),
+		};
+
+		if let Err(error) = self.sender.lock().send(report) {
+			error!("Unable to report status of job {}. {}", &item.id, error);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn state(status: ExecutionStatus, provider: StatusProvider) -> Option<&'static str> {
+		CommitState::from_status(&status).map(|state| state.as_str(provider))
+	}
+
+	#[test]
+	fn as_str_uses_the_names_of_each_provider() {
+		for provider in [StatusProvider::GitHub, StatusProvider::Gitea].iter() {
+			let provider = *provider;
+			assert_eq!(state(ExecutionStatus::Queued, provider), Some("pending"));
+			assert_eq!(state(ExecutionStatus::Running, provider), Some("pending"));
+			assert_eq!(state(ExecutionStatus::Completed, provider), Some("success"));
+			assert_eq!(state(ExecutionStatus::Failed(1), provider), Some("failure"));
+			assert_eq!(state(ExecutionStatus::Cancelled, provider), Some("failure"));
+		}
+
+		let gitlab = StatusProvider::GitLab;
+		assert_eq!(
+			state(ExecutionStatus::AwaitingApproval, gitlab),
+			Some("pending")
+		);
+		assert_eq!(state(ExecutionStatus::Running, gitlab), Some("running"));
+		assert_eq!(state(ExecutionStatus::Completed, gitlab), Some("success"));
+		assert_eq!(state(ExecutionStatus::Failed(1), gitlab), Some("failed"));
+		assert_eq!(state(ExecutionStatus::Unknown, gitlab), None);
+	}
+
+	#[test]
+	fn as_str_finishes_skipped_jobs() {
+		let skipped = || ExecutionStatus::Skipped("No changes".into());
+		assert_eq!(state(skipped(), StatusProvider::GitLab), Some("skipped"));
+		assert_eq!(state(skipped(), StatusProvider::Gitea), Some("warning"));
+		// GitHub has no neutral state
+		assert_eq!(state(skipped(), StatusProvider::GitHub), Some("success"));
+		assert_eq!(description(&skipped()), "Skipped. No changes");
+	}
+
+	#[test]
+	fn context_includes_the_matrix_combination() {
+		let settings: StatusReporting =
+			serde_json::from_str(r#"{"provider": "github", "repository": "a/b", "token": "t"}"#)
+				.unwrap();
+		assert_eq!(context(&settings, &HashMap::new()), "littleci");
+
+		let mut matrix = HashMap::new();
+		matrix.insert("rust".to_string(), "stable".to_string());
+		matrix.insert("os".to_string(), "linux".to_string());
+		assert_eq!(
+			context(&settings, &matrix),
+			"littleci (os=linux, rust=stable)"
+		);
+	}
+}
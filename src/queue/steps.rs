@@ -0,0 +1,420 @@
+use failure::{format_err, Error};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use super::ExecutionStatus;
+use crate::config::Step;
+
+/// The status of a single step within a job.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StepState {
+	pub name: String,
+
+	/// Steps which must complete before this step is started
+	pub needs: Vec<String>,
+
+	#[serde(flatten)]
+	pub status: ExecutionStatus,
+}
+
+impl From<&Step> for StepState {
+	fn from(step: &Step) -> Self {
+		Self {
+			name: step.name.clone(),
+			needs: step.needs.clone(),
+			status: ExecutionStatus::Queued,
+		}
+	}
+}
+
+/// Check that step names are unique, that every dependency exists and that there are no cycles.
+pub fn validate(steps: &[Step]) -> Result<(), Error> {
+	let mut names = HashSet::new();
+	for step in steps.iter() {
+		if step.name.is_empty() {
+			return Err(format_err!("Steps must have a name"));
+		}
+		// Step names are used for log file names
+		if !step
+			.name
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+		{
+			return Err(format_err!(
+				"Step `{}` may only contain letters, numbers, `-` and `_`",
+				step.name
+			));
+		}
+		if !names.insert(step.name.as_str()) {
+			return Err(format_err!("Step `{}` is defined more than once", step.name));
+		}
+	}
+
+	for step in steps.iter() {
+		for need in step.needs.iter() {
+			if !names.contains(need.as_str()) {
+				return Err(format_err!(
+					"Step `{}` needs `{}` which doesn't exist",
+					step.name,
+					need
+				));
+			}
+		}
+	}
+
+	// Repeatedly remove steps whose dependencies have all been removed. Anything left over is
+	// part of a cycle.
+	let mut resolved: HashSet<&str> = HashSet::new();
+	loop {
+		let ready: Vec<&str> = steps
+			.iter()
+			.filter(|s| !resolved.contains(s.name.as_str()))
+			.filter(|s| s.needs.iter().all(|n| resolved.contains(n.as_str())))
+			.map(|s| s.name.as_str())
+			.collect();
+
+		if ready.is_empty() {
+			break;
+		}
+		resolved.extend(ready);
+	}
+
+	match steps.iter().find(|s| !resolved.contains(s.name.as_str())) {
+		Some(step) => Err(format_err!("Step `{}` is part of a dependency cycle", step.name)),
+		None => Ok(()),
+	}
+}
+
+fn is_finished(status: &ExecutionStatus) -> bool {
+	match status {
+		ExecutionStatus::Queued | ExecutionStatus::AwaitingApproval | ExecutionStatus::Running => {
+			false
+		}
+		_ => true,
+	}
+}
+
+enum StepEvent {
+	Started(usize),
+	Finished(usize, ExecutionStatus),
+}
+
+/// Run the steps as a dependency graph. Steps are started as soon as all of the steps they need
+/// have completed, with at most `max_parallel` steps running at once. Steps which need a step
+/// that didn't complete are skipped. Steps which require approval wait until `await_approval`
+/// returns, and are cancelled if it returns `false`. `on_update` is called whenever the status of
+/// a step changes.
+pub fn execute<R, A, U>(
+	steps: &[Step],
+	max_parallel: usize,
+	run_step: R,
+	await_approval: A,
+	mut on_update: U,
+) -> Vec<StepState>
+where
+	R: Fn(&Step) -> ExecutionStatus + Send + Sync + 'static,
+	A: Fn(&Step) -> bool + Send + Sync + 'static,
+	U: FnMut(&[StepState]),
+{
+	let run_step = Arc::new(run_step);
+	let await_approval = Arc::new(await_approval);
+	let max_parallel = max_parallel.max(1);
+
+	let mut states: Vec<StepState> = steps.iter().map(StepState::from).collect();
+	let indexes: HashMap<String, usize> = steps
+		.iter()
+		.enumerate()
+		.map(|(index, step)| (step.name.clone(), index))
+		.collect();
+
+	let (sender, receiver) = channel::<StepEvent>();
+	let mut running = 0;
+
+	loop {
+		let mut changed = false;
+
+		for index in 0..states.len() {
+			match states[index].status {
+				ExecutionStatus::Queued => {}
+				_ => continue,
+			}
+
+			let needs: Vec<&ExecutionStatus> = states[index]
+				.needs
+				.iter()
+				.map(|need| &states[indexes[need]].status)
+				.collect();
+
+			let failed_need = states[index]
+				.needs
+				.iter()
+				.zip(needs.iter())
+				.find(|(_, status)| match status {
+					ExecutionStatus::Queued
+					| ExecutionStatus::AwaitingApproval
+					| ExecutionStatus::Running
+					| ExecutionStatus::Completed => false,
+					_ => true,
+				})
+				.map(|(need, _)| need.clone());
+
+			if let Some(need) = failed_need {
+				info!("Skipping step {}. Step {} didn't complete.", &states[index].name, &need);
+				states[index].status =
+					ExecutionStatus::Skipped(format!("Step `{}` didn't complete", need));
+				changed = true;
+				continue;
+			}
+
+			let ready = needs.iter().all(|status| match status {
+				ExecutionStatus::Completed => true,
+				_ => false,
+			});
+
+			if ready && running < max_parallel {
+				let step = steps[index].clone();
+				states[index].status = if step.approval {
+					info!("Step {} is awaiting approval", &step.name);
+					ExecutionStatus::AwaitingApproval
+				} else {
+					debug!("Starting step {}", &step.name);
+					ExecutionStatus::Running
+				};
+				running += 1;
+				changed = true;
+
+				let run_step = run_step.clone();
+				let await_approval = await_approval.clone();
+				let sender = sender.clone();
+				thread::spawn(move || {
+					let status = if step.approval && !await_approval(&step) {
+						info!("Step {} was rejected", &step.name);
+						ExecutionStatus::Cancelled
+					} else {
+						if step.approval && sender.send(StepEvent::Started(index)).is_err() {
+							error!("Unable to report status of step {}", &step.name);
+						}
+						run_step(&step)
+					};
+					if sender.send(StepEvent::Finished(index, status)).is_err() {
+						error!("Unable to report status of step {}", &step.name);
+					}
+				});
+			}
+		}
+
+		if changed {
+			on_update(&states);
+			// Skipping a step may allow other steps to be skipped, so check again
+			continue;
+		}
+
+		if running == 0 {
+			break;
+		}
+
+		match receiver.recv() {
+			Ok(StepEvent::Started(index)) => {
+				debug!("Starting step {}", &states[index].name);
+				states[index].status = ExecutionStatus::Running;
+				on_update(&states);
+			}
+			Ok(StepEvent::Finished(index, status)) => {
+				debug!("Step {} finished", &states[index].name);
+				states[index].status = status;
+				running -= 1;
+				on_update(&states);
+			}
+			Err(error) => {
+				error!("Unable to receive step status. {}", error);
+				break;
+			}
+		}
+	}
+
+	// If anything went wrong, don't leave steps queued or running
+	for state in states.iter_mut().filter(|s| !is_finished(&s.status)) {
+		state.status = ExecutionStatus::Unknown;
+	}
+
+	states
+}
+
+/// The status of a job given the status of its steps: the first failure or cancellation,
+/// otherwise completed.
+pub fn combined_status(states: &[StepState]) -> ExecutionStatus {
+	states
+		.iter()
+		.find(|s| match s.status {
+			ExecutionStatus::Failed(_) | ExecutionStatus::Cancelled | ExecutionStatus::Unknown => {
+				true
+			}
+			_ => false,
+		})
+		.map(|s| s.status.clone())
+		.unwrap_or(ExecutionStatus::Completed)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use parking_lot::Mutex;
+
+	fn step(name: &str, needs: &[&str]) -> Step {
+		Step {
+			name: name.into(),
+			run: format!("echo {}", name),
+			needs: needs.iter().map(|need| need.to_string()).collect(),
+			approval: false,
+		}
+	}
+
+	fn statuses(states: &[StepState]) -> Vec<(&str, String)> {
+		states
+			.iter()
+			.map(|state| {
+				let status = match &state.status {
+					ExecutionStatus::Skipped(reason) => format!("Skipped. {}", reason),
+					status => format!("{:?}", status),
+				};
+				(state.name.as_str(), status)
+			})
+			.collect()
+	}
+
+	#[test]
+	fn validate_accepts_a_dependency_graph() {
+		let steps = vec![
+			step("build", &[]),
+			step("lint", &[]),
+			step("test", &["build"]),
+			step("deploy", &["test", "lint"]),
+		];
+		assert!(validate(&steps).is_ok());
+	}
+
+	#[test]
+	fn validate_rejects_unknown_dependencies() {
+		let steps = vec![step("build", &[]), step("test", &["compile"])];
+		let error = validate(&steps).unwrap_err();
+		assert_eq!(
+			error.to_string(),
+			"Step `test` needs `compile` which doesn't exist"
+		);
+	}
+
+	#[test]
+	fn validate_rejects_cycles() {
+		let steps = vec![
+			step("build", &["deploy"]),
+			step("test", &["build"]),
+			step("deploy", &["test"]),
+		];
+		let error = validate(&steps).unwrap_err();
+		assert!(error.to_string().contains("is part of a dependency cycle"));
+
+		let steps = vec![step("build", &["build"])];
+		assert!(validate(&steps).is_err());
+	}
+
+	#[test]
+	fn validate_rejects_duplicate_and_invalid_names() {
+		assert!(validate(&[step("build", &[]), step("build", &[])]).is_err());
+		assert!(validate(&[step("", &[])]).is_err());
+		assert!(validate(&[step("../build", &[])]).is_err());
+	}
+
+	#[test]
+	fn execute_runs_steps_after_their_dependencies() {
+		let steps = vec![
+			step("deploy", &["test"]),
+			step("test", &["build"]),
+			step("build", &[]),
+		];
+		let order = Arc::new(Mutex::new(Vec::new()));
+		let run_order = order.clone();
+
+		let states = execute(
+			&steps,
+			1,
+			move |step| {
+				run_order.lock().push(step.name.clone());
+				ExecutionStatus::Completed
+			},
+			|_| true,
+			|_| {},
+		);
+
+		assert_eq!(*order.lock(), vec!["build", "test", "deploy"]);
+		assert_eq!(
+			statuses(&states),
+			vec![
+				("deploy", "Completed".to_string()),
+				("test", "Completed".to_string()),
+				("build", "Completed".to_string()),
+			]
+		);
+	}
+
+	#[test]
+	fn execute_skips_steps_which_need_a_failed_step() {
+		let steps = vec![
+			step("build", &[]),
+			step("lint", &[]),
+			step("test", &["build"]),
+			step("deploy", &["test", "lint"]),
+		];
+
+		let states = execute(
+			&steps,
+			2,
+			|step| match step.name.as_str() {
+				"build" => ExecutionStatus::Failed(1),
+				_ => ExecutionStatus::Completed,
+			},
+			|_| true,
+			|_| {},
+		);
+
+		assert_eq!(
+			statuses(&states),
+			vec![
+				("build", "Failed(1)".to_string()),
+				("lint", "Completed".to_string()),
+				("test", "Skipped. Step `build` didn't complete".to_string()),
+				("deploy", "Skipped. Step `test` didn't complete".to_string()),
+			]
+		);
+		match combined_status(&states) {
+			ExecutionStatus::Failed(1) => {}
+			status => panic!("Unexpected status {:?}", status),
+		}
+	}
+
+	#[test]
+	fn execute_cancels_rejected_steps() {
+		let mut deploy = step("deploy", &["build"]);
+		deploy.approval = true;
+		let steps = vec![step("build", &[]), deploy, step("notify", &["deploy"])];
+
+		let states = execute(&steps, 1, |_| ExecutionStatus::Completed, |_| false, |_| {});
+
+		assert_eq!(
+			statuses(&states),
+			vec![
+				("build", "Completed".to_string()),
+				("deploy", "Cancelled".to_string()),
+				(
+					"notify",
+					"Skipped. Step `deploy` didn't complete".to_string()
+				),
+			]
+		);
+	}
+}
@@ -0,0 +1,457 @@
+use failure::{format_err, Error};
+use glob::{MatchOptions, Pattern};
+use regex::Regex;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use super::ArbitraryData;
+use crate::config::{GitTrigger, PathTrigger, Trigger};
+
+/// The kind of git reference a notification was sent for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RefKind {
+	Head,
+	Tag,
+	/// A pull request into a branch
+	PullRequest,
+}
+
+/// What a push did to a branch or tag.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PushType {
+	Push,
+	Create,
+	Delete,
+	ForcePush,
+}
+
+impl PushType {
+	/// Detect creations and deletions from the commits before and after the push, which are all
+	/// zeros when the reference didn't exist.
+	pub fn detect(before: &str, after: &str, forced: bool) -> Self {
+		let is_null = |sha: &str| !sha.is_empty() && sha.chars().all(|c| c == '0');
+		if is_null(after) {
+			PushType::Delete
+		} else if is_null(before) {
+			PushType::Create
+		} else if forced {
+			PushType::ForcePush
+		} else {
+			PushType::Push
+		}
+	}
+
+	/// Parse the value of `LITTLECI_GIT_EVENT`
+	pub fn parse(event: &str) -> Option<Self> {
+		match event {
+			"push" => Some(PushType::Push),
+			"create" => Some(PushType::Create),
+			"delete" => Some(PushType::Delete),
+			"force_push" => Some(PushType::ForcePush),
+			_ => None,
+		}
+	}
+
+	/// Passed to jobs as `LITTLECI_GIT_EVENT`
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			PushType::Push => "push",
+			PushType::Create => "create",
+			PushType::Delete => "delete",
+			PushType::ForcePush => "force_push",
+		}
+	}
+}
+
+/// A branch, tag or path pattern. Patterns starting with `^` are regular expressions and
+/// anything else is a glob, where `*` doesn't match `/` but `**` does.
+enum NamePattern {
+	Glob(Pattern),
+	Regex(Regex),
+}
+
+impl NamePattern {
+	fn parse(pattern: &str) -> Result<Self, Error> {
+		if pattern.starts_with('^') {
+			Regex::new(pattern)
+				.map(NamePattern::Regex)
+				.map_err(|error| format_err!("Invalid pattern `{}`. {}", pattern, error))
+		} else {
+			Pattern::new(pattern)
+				.map(NamePattern::Glob)
+				.map_err(|error| format_err!("Invalid pattern `{}`. {}", pattern, error))
+		}
+	}
+
+	fn matches(&self, name: &str) -> bool {
+		match self {
+			NamePattern::Glob(pattern) => pattern.matches_with(
+				name,
+				MatchOptions {
+					case_sensitive: true,
+					require_literal_separator: true,
+					require_literal_leading_dot: false,
+				},
+			),
+			NamePattern::Regex(pattern) => pattern.is_match(name),
+		}
+	}
+}
+
+/// Split branch or tag patterns into inclusions and exclusions, which start with `!`.
+fn parse_patterns(patterns: &[String]) -> Result<(Vec<NamePattern>, Vec<NamePattern>), Error> {
+	let mut include = vec![];
+	let mut exclude = vec![];
+	for pattern in patterns.iter() {
+		if pattern.starts_with('!') {
+			exclude.push(NamePattern::parse(&pattern[1..])?);
+		} else {
+			include.push(NamePattern::parse(pattern)?);
+		}
+	}
+	Ok((include, exclude))
+}
+
+/// A name matches when it matches any inclusion, or there are only exclusions, and doesn't
+/// match any exclusion.
+pub fn matches_patterns(patterns: &[String], name: &str) -> Result<bool, Error> {
+	let (include, exclude) = parse_patterns(patterns)?;
+
+	let included = include.is_empty() || include.iter().any(|p| p.matches(name));
+	let excluded = exclude.iter().any(|p| p.matches(name));
+
+	Ok(included && !excluded)
+}
+
+fn parse_path_patterns(patterns: &[String]) -> Result<Vec<NamePattern>, Error> {
+	patterns.iter().map(|p| NamePattern::parse(p)).collect()
+}
+
+/// Whether any changed file matches an include pattern, or there are none, and doesn't match
+/// an exclude pattern.
+fn matches_paths(trigger: &PathTrigger, changed_files: &[String]) -> Result<bool, Error> {
+	let include = parse_path_patterns(&trigger.include)?;
+	let exclude = parse_path_patterns(&trigger.exclude)?;
+
+	Ok(changed_files.iter().any(|file| {
+		(include.is_empty() || include.iter().any(|p| p.matches(file)))
+			&& !exclude.iter().any(|p| p.matches(file))
+	}))
+}
+
+/// Check that the branch, tag and path patterns of all git triggers are valid.
+pub fn validate(triggers: &[Trigger]) -> Result<(), Error> {
+	for trigger in triggers.iter() {
+		match trigger {
+			Trigger::Git(GitTrigger::Head(patterns))
+			| Trigger::Git(GitTrigger::Tags(patterns))
+			| Trigger::Git(GitTrigger::PullRequest(patterns))
+			| Trigger::Git(GitTrigger::Delete(patterns))
+			| Trigger::Poll { refs: patterns, .. } => {
+				parse_patterns(patterns)?;
+			}
+			Trigger::Git(GitTrigger::Paths(trigger)) => {
+				parse_path_patterns(&trigger.include)?;
+				parse_path_patterns(&trigger.exclude)?;
+				parse_patterns(&trigger.head)?;
+			}
+			_ => {}
+		}
+	}
+	Ok(())
+}
+
+/// A push to a branch or tag, or a pull request into a branch.
+#[derive(Debug, Clone)]
+pub struct GitEvent {
+	pub kind: RefKind,
+	/// Branch or tag name. The base branch for pull requests.
+	pub name: String,
+	/// Files changed by the pushed commits, if the notification includes them
+	pub changed_files: Option<Vec<String>>,
+	pub push_type: PushType,
+}
+
+impl GitEvent {
+	/// The event described by job data, eg. from a generic notification. `None` if the data
+	/// doesn't name a branch, tag or pull request.
+	pub fn from_data(data: &ArbitraryData) -> Option<Self> {
+		let data = data.inner();
+		let event = data.get("LITTLECI_GIT_EVENT").map(String::as_str);
+
+		let (kind, name) = match (event, data.get("LITTLECI_PR_BASE_REF")) {
+			(Some("pull_request"), Some(base)) => (RefKind::PullRequest, base),
+			_ => match (
+				data.get("LITTLECI_GIT_TAG"),
+				data.get("LITTLECI_GIT_BRANCH"),
+			) {
+				(Some(tag), _) => (RefKind::Tag, tag),
+				(None, Some(branch)) => (RefKind::Head, branch),
+				(None, None) => return None,
+			},
+		};
+
+		let changed_files = data.get("LITTLECI_GIT_CHANGED_FILES").map(|files| {
+			files
+				.lines()
+				.filter(|file| !file.is_empty())
+				.map(String::from)
+				.collect()
+		});
+
+		Some(Self {
+			kind,
+			name: name.clone(),
+			changed_files,
+			push_type: event.and_then(PushType::parse).unwrap_or(PushType::Push),
+		})
+	}
+}
+
+/// Whether a single git trigger matches a push.
+fn git_trigger_matches(trigger: &GitTrigger, event: &GitEvent) -> Result<bool, Error> {
+	// Deleted references have nothing to build, so only triggers which opt in match them
+	if event.push_type == PushType::Delete {
+		return match trigger {
+			GitTrigger::Delete(patterns) => matches_patterns(patterns, &event.name),
+			_ => Ok(false),
+		};
+	}
+
+	match (trigger, event.kind) {
+		(GitTrigger::Any, RefKind::Head) | (GitTrigger::Any, RefKind::Tag) => Ok(true),
+		(GitTrigger::Tag, RefKind::Tag) => Ok(true),
+		(GitTrigger::Head(patterns), RefKind::Head) => matches_patterns(patterns, &event.name),
+		(GitTrigger::Tags(patterns), RefKind::Tag) => matches_patterns(patterns, &event.name),
+		(GitTrigger::Paths(trigger), RefKind::Head) => {
+			if !matches_patterns(&trigger.head, &event.name)? {
+				return Ok(false);
+			}
+			match &event.changed_files {
+				Some(changed_files) => matches_paths(trigger, changed_files),
+				// Without the list of changed files, run rather than risk missing a change
+				None => Ok(true),
+			}
+		}
+		(GitTrigger::PullRequest(patterns), RefKind::PullRequest) => {
+			matches_patterns(patterns, &event.name)
+		}
+		_ => Ok(false),
+	}
+}
+
+/// Whether any of a repository's triggers match a push or pull request.
+pub fn matches_ref(repository_slug: &str, triggers: &[Trigger], event: &GitEvent) -> bool {
+	for trigger in triggers.iter() {
+		let matched = match trigger {
+			// Pull requests would otherwise queue a second job for every push
+			Trigger::Any => {
+				Ok(event.kind != RefKind::PullRequest && event.push_type != PushType::Delete)
+			}
+			Trigger::Git(git_trigger) => git_trigger_matches(git_trigger, event),
+			// Scheduled and polled jobs are queued in the background, not by notifications
+			Trigger::Schedule { .. } | Trigger::Poll { .. } => Ok(false),
+		};
+
+		match matched {
+			Ok(true) => {
+				debug!(
+					"Matched trigger {:?} with {} of {:?} {} for repository {}",
+					trigger,
+					event.push_type.as_str(),
+					event.kind,
+					event.name,
+					repository_slug
+				);
+				return true;
+			}
+			Ok(false) => {}
+			Err(error) => error!(
+				"Invalid trigger for repository {}. {}",
+				repository_slug, error
+			),
+		}
+	}
+	false
+}
+
+/// Why an event shouldn't queue a job, if none of the repository's triggers match it.
+pub fn skip_reason(
+	repository_slug: &str,
+	triggers: &[Trigger],
+	event: &GitEvent,
+) -> Option<String> {
+	if matches_ref(repository_slug, triggers, event) {
+		None
+	} else {
+		Some(format!(
+			"Trigger rules not matched for {} of `{}`. No job queued",
+			event.push_type.as_str(),
+			&event.name
+		))
+	}
+}
+
+/// Why a generic notification shouldn't queue a job. Triggers are only evaluated when the data
+/// names a branch, tag or pull request, and the repository has triggers which notifications can
+/// match, so notifications without git data queue jobs as they always have.
+pub fn data_skip_reason(
+	repository_slug: &str,
+	triggers: &[Trigger],
+	data: &ArbitraryData,
+) -> Option<String> {
+	let has_notification_triggers = triggers.iter().any(|trigger| match trigger {
+		Trigger::Schedule { .. } | Trigger::Poll { .. } => false,
+		_ => true,
+	});
+	if !has_notification_triggers {
+		return None;
+	}
+
+	GitEvent::from_data(data).and_then(|event| skip_reason(repository_slug, triggers, &event))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn patterns(patterns: &[&str]) -> Vec<String> {
+		patterns.iter().map(|pattern| pattern.to_string()).collect()
+	}
+
+	#[test]
+	fn matches_patterns_with_globs() {
+		let release = patterns(&["master", "release/*"]);
+		assert!(matches_patterns(&release, "master").unwrap());
+		assert!(matches_patterns(&release, "release/1.0").unwrap());
+		assert!(!matches_patterns(&release, "release/1.0/hotfix").unwrap());
+		assert!(!matches_patterns(&release, "feature/login").unwrap());
+
+		let nested = patterns(&["feature/**"]);
+		assert!(matches_patterns(&nested, "feature/users/login").unwrap());
+	}
+
+	#[test]
+	fn matches_patterns_with_regular_expressions() {
+		let versions = patterns(&[r"^v\d+\.\d+\.\d+$"]);
+		assert!(matches_patterns(&versions, "v1.20.3").unwrap());
+		assert!(!matches_patterns(&versions, "v1.20.3-beta").unwrap());
+	}
+
+	#[test]
+	fn matches_patterns_with_exclusions() {
+		let all_but_wip = patterns(&["!wip/*"]);
+		assert!(matches_patterns(&all_but_wip, "master").unwrap());
+		assert!(!matches_patterns(&all_but_wip, "wip/parser").unwrap());
+
+		let releases_but_beta = patterns(&["release/*", "!release/*-beta"]);
+		assert!(matches_patterns(&releases_but_beta, "release/2.0").unwrap());
+		assert!(!matches_patterns(&releases_but_beta, "release/2.0-beta").unwrap());
+
+		// Without any patterns everything matches
+		assert!(matches_patterns(&[], "anything").unwrap());
+	}
+
+	#[test]
+	fn matches_patterns_rejects_invalid_patterns() {
+		assert!(matches_patterns(&patterns(&["release/[*"]), "release/1").is_err());
+		assert!(matches_patterns(&patterns(&["^(unclosed"]), "unclosed").is_err());
+	}
+
+	#[test]
+	fn push_type_detects_creations_and_deletions() {
+		let null = "0000000000000000000000000000000000000000";
+		let before = "8d5b1f2a9c3e4d6f7a8b9c0d1e2f3a4b5c6d7e8f";
+		let after = "1f2e3d4c5b6a79880a1b2c3d4e5f60718293a4b5";
+
+		assert_eq!(PushType::detect(before, after, false), PushType::Push);
+		assert_eq!(PushType::detect(before, after, true), PushType::ForcePush);
+		assert_eq!(PushType::detect(null, after, false), PushType::Create);
+		assert_eq!(PushType::detect(before, null, false), PushType::Delete);
+		// Deletions and creations aren't force pushes
+		assert_eq!(PushType::detect(before, null, true), PushType::Delete);
+		assert_eq!(PushType::detect(null, after, true), PushType::Create);
+		// Missing commits aren't null commits
+		assert_eq!(PushType::detect("", "", false), PushType::Push);
+	}
+
+	#[test]
+	fn push_type_round_trips_through_its_name() {
+		for push_type in [
+			PushType::Push,
+			PushType::Create,
+			PushType::Delete,
+			PushType::ForcePush,
+		]
+		.iter()
+		{
+			assert_eq!(PushType::parse(push_type.as_str()), Some(*push_type));
+		}
+		assert_eq!(PushType::parse("pull_request"), None);
+	}
+
+	fn event(variables: &[(&str, &str)]) -> Option<GitEvent> {
+		let data = variables
+			.iter()
+			.map(|(name, value)| (name.to_string(), value.to_string()))
+			.collect();
+		GitEvent::from_data(&ArbitraryData::new(data))
+	}
+
+	#[test]
+	fn git_event_from_branch_and_tag_data() {
+		let push = event(&[
+			("LITTLECI_GIT_BRANCH", "master"),
+			("LITTLECI_GIT_EVENT", "force_push"),
+			("LITTLECI_GIT_CHANGED_FILES", "src/main.rs\n\nREADME.md"),
+		])
+		.unwrap();
+		assert_eq!(push.kind, RefKind::Head);
+		assert_eq!(push.name, "master");
+		assert_eq!(push.push_type, PushType::ForcePush);
+		assert_eq!(
+			push.changed_files,
+			Some(vec!["src/main.rs".to_string(), "README.md".to_string()])
+		);
+
+		// Tags take precedence, and unknown events are treated as pushes
+		let tag = event(&[
+			("LITTLECI_GIT_TAG", "v1.0.0"),
+			("LITTLECI_GIT_BRANCH", "master"),
+			("LITTLECI_GIT_EVENT", "unknown"),
+		])
+		.unwrap();
+		assert_eq!(tag.kind, RefKind::Tag);
+		assert_eq!(tag.name, "v1.0.0");
+		assert_eq!(tag.push_type, PushType::Push);
+		assert_eq!(tag.changed_files, None);
+	}
+
+	#[test]
+	fn git_event_from_pull_request_data() {
+		let pull_request = event(&[
+			("LITTLECI_GIT_EVENT", "pull_request"),
+			("LITTLECI_PR_BASE_REF", "master"),
+			("LITTLECI_PR_HEAD_REF", "feature"),
+		])
+		.unwrap();
+		assert_eq!(pull_request.kind, RefKind::PullRequest);
+		assert_eq!(pull_request.name, "master");
+
+		// The base branch is only used for pull request events
+		let push = event(&[
+			("LITTLECI_PR_BASE_REF", "master"),
+			("LITTLECI_GIT_BRANCH", "feature"),
+		])
+		.unwrap();
+		assert_eq!(push.kind, RefKind::Head);
+		assert_eq!(push.name, "feature");
+	}
+
+	#[test]
+	fn git_event_requires_a_reference() {
+		assert!(event(&[]).is_none());
+		assert!(event(&[("LITTLECI_GIT_EVENT", "pull_request")]).is_none());
+	}
+}
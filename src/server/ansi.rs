@@ -0,0 +1,289 @@
+use rocket::FromFormValue;
+use std::iter::Peekable;
+use std::str::Chars;
+
+const ESCAPE: char = '\x1b';
+const BELL: char = '\x07';
+
+const COLOR_NAMES: [&str; 8] = [
+	"black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+/// Format used when rendering a job's output log.
+#[derive(FromFormValue, Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+	/// Output as written by the job, escape sequences included
+	Raw,
+	/// Output with all escape sequences stripped
+	Plain,
+	/// HTML-escaped output with colors converted to classed `<span>`s
+	Html,
+}
+
+impl Default for OutputFormat {
+	fn default() -> Self {
+		Self::Raw
+	}
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Style {
+	bold: bool,
+	dim: bool,
+	italic: bool,
+	underline: bool,
+	foreground: Option<String>,
+	background: Option<String>,
+}
+
+impl Style {
+	fn is_default(&self) -> bool {
+		*self == Style::default()
+	}
+
+	fn classes(&self) -> Vec<String> {
+		let mut classes = Vec::new();
+		if self.bold {
+			classes.push("ansi-bold".into());
+		}
+		if self.dim {
+			classes.push("ansi-dim".into());
+		}
+		if self.italic {
+			classes.push("ansi-italic".into());
+		}
+		if self.underline {
+			classes.push("ansi-underline".into());
+		}
+		if let Some(foreground) = &self.foreground {
+			classes.push(format!("ansi-fg-{}", foreground));
+		}
+		if let Some(background) = &self.background {
+			classes.push(format!("ansi-bg-{}", background));
+		}
+		classes
+	}
+
+	/// Apply the parameters of an SGR (`ESC[...m`) sequence.
+	fn apply(&mut self, params: &str) {
+		let mut codes = params
+			.split(';')
+			.map(|code| code.parse::<u16>().unwrap_or(0))
+			.collect::<Vec<u16>>()
+			.into_iter();
+
+		while let Some(code) = codes.next() {
+			match code {
+				0 => *self = Style::default(),
+				1 => self.bold = true,
+				2 => self.dim = true,
+				3 => self.italic = true,
+				4 => self.underline = true,
+				22 => {
+					self.bold = false;
+					self.dim = false;
+				}
+				23 => self.italic = false,
+				24 => self.underline = false,
+				30..=37 => self.foreground = Some(COLOR_NAMES[(code - 30) as usize].into()),
+				39 => self.foreground = None,
+				40..=47 => self.background = Some(COLOR_NAMES[(code - 40) as usize].into()),
+				49 => self.background = None,
+				90..=97 => {
+					self.foreground = Some(format!("bright-{}", COLOR_NAMES[(code - 90) as usize]))
+				}
+				100..=107 => {
+					self.background = Some(format!("bright-{}", COLOR_NAMES[(code - 100) as usize]))
+				}
+				38 | 48 => {
+					let color = match codes.next() {
+						// 256 color palette
+						Some(5) => codes.next().map(|index| format!("{}", index)),
+						// 24-bit colors don't map onto a fixed set of classes, so drop them
+						Some(2) => {
+							codes.next();
+							codes.next();
+							codes.next();
+							None
+						}
+						_ => None,
+					};
+					if code == 38 {
+						self.foreground = color;
+					} else {
+						self.background = color;
+					}
+				}
+				_ => {}
+			}
+		}
+	}
+}
+
+enum Token {
+	Text(char),
+	Sgr(String),
+}
+
+/// Read the next printable character or SGR sequence, silently consuming any other escape
+/// sequences.
+fn next_token(chars: &mut Peekable<Chars>) -> Option<Token> {
+	loop {
+		let c = chars.next()?;
+		if c != ESCAPE {
+			return Some(Token::Text(c));
+		}
+
+		match chars.next() {
+			// Control Sequence Introducer, terminated by a byte in the range 0x40-0x7E
+			Some('[') => {
+				let mut params = String::new();
+				while let Some(c) = chars.next() {
+					if ('\x40'..='\x7e').contains(&c) {
+						if c == 'm' {
+							return Some(Token::Sgr(params));
+						}
+						break;
+					}
+					params.push(c);
+				}
+			}
+			// Operating System Command, terminated by BEL or ST (`ESC\`)
+			Some(']') => {
+				while let Some(c) = chars.next() {
+					if c == BELL {
+						break;
+					}
+					if c == ESCAPE && chars.peek() == Some(&'\\') {
+						chars.next();
+						break;
+					}
+				}
+			}
+			// Any other two character escape sequence
+			_ => {}
+		}
+	}
+}
+
+fn escape_html(c: char, output: &mut String) {
+	match c {
+		'&' => output.push_str("&amp;"),
+		'<' => output.push_str("&lt;"),
+		'>' => output.push_str("&gt;"),
+		'"' => output.push_str("&quot;"),
+		'\'' => output.push_str("&#39;"),
+		_ => output.push(c),
+	}
+}
+
+/// Remove all ANSI escape sequences from `input`.
+pub fn strip(input: &str) -> String {
+	let mut output = String::with_capacity(input.len());
+	let mut chars = input.chars().peekable();
+	while let Some(token) = next_token(&mut chars) {
+		if let Token::Text(c) = token {
+			output.push(c);
+		}
+	}
+	output
+}
+
+/// Convert `input` to HTML. Text is escaped and SGR styles are converted into `<span>` elements
+/// with `ansi-*` classes, eg. `<span class="ansi-bold ansi-fg-red">`.
+pub fn to_html(input: &str) -> String {
+	let mut output = String::with_capacity(input.len());
+	let mut chars = input.chars().peekable();
+	let mut style = Style::default();
+	let mut span_open = false;
+
+	while let Some(token) = next_token(&mut chars) {
+		match token {
+			Token::Text(c) => {
+				if !span_open && !style.is_default() {
+					output.push_str(&format!(
+						"<span class=\"{}\">",
+						style.classes().join(" ")
+					));
+					span_open = true;
+				}
+				escape_html(c, &mut output);
+			}
+			Token::Sgr(params) => {
+				let mut new_style = style.clone();
+				new_style.apply(&params);
+				if new_style != style {
+					if span_open {
+						output.push_str("</span>");
+						span_open = false;
+					}
+					style = new_style;
+				}
+			}
+		}
+	}
+
+	if span_open {
+		output.push_str("</span>");
+	}
+
+	output
+}
+
+/// Render a log in the requested format.
+pub fn render(input: String, format: OutputFormat) -> String {
+	match format {
+		OutputFormat::Raw => input,
+		OutputFormat::Plain => strip(&input),
+		OutputFormat::Html => to_html(&input),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn strip_removes_escape_sequences() {
+		assert_eq!(strip("plain"), "plain");
+		assert_eq!(strip("\x1b[1;31merror\x1b[0m: failed"), "error: failed");
+		// Cursor movement, erasing and window titles
+		assert_eq!(strip("\x1b[2K\x1b[1Gdone"), "done");
+		assert_eq!(strip("\x1b]0;title\x07text"), "text");
+		assert_eq!(strip("\x1b]0;title\x1b\\text"), "text");
+	}
+
+	#[test]
+	fn to_html_escapes_text() {
+		assert_eq!(
+			to_html("<a href=\"x\">Tom & Jerry's</a>"),
+			"&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+		);
+	}
+
+	#[test]
+	fn to_html_converts_styles_to_spans() {
+		assert_eq!(
+			to_html("\x1b[1;31merror\x1b[0m: failed"),
+			"<span class=\"ansi-bold ansi-fg-red\">error</span>: failed"
+		);
+		assert_eq!(
+			to_html("\x1b[92;44mok\x1b[39mblue\x1b[49m"),
+			"<span class=\"ansi-fg-bright-green ansi-bg-blue\">ok</span><span class=\"ansi-bg-blue\">blue</span>"
+		);
+		assert_eq!(
+			to_html("\x1b[38;5;208morange\x1b[38;2;1;2;3mtrue color"),
+			"<span class=\"ansi-fg-208\">orange</span>true color"
+		);
+	}
+
+	#[test]
+	fn to_html_closes_unterminated_spans() {
+		assert_eq!(
+			to_html("\x1b[4munderlined"),
+			"<span class=\"ansi-underline\">underlined</span>"
+		);
+		// Styles without text don't produce empty spans
+		assert_eq!(to_html("\x1b[1m\x1b[0mtext"), "text");
+	}
+}
@@ -1,6 +1,15 @@
 use regex::Regex;
+use rocket::data::{self, FromDataSimple};
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::{Data, Outcome};
 use serde::de::Error;
 use serde::{self, Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::io::Read;
+
+use crate::queue::triggers::PushType;
+use crate::queue::ArbitraryData;
 
 #[derive(Debug, Clone)]
 pub enum GitReference {
@@ -9,6 +18,28 @@ pub enum GitReference {
 	// TODO Do we need more ref types?
 }
 
+impl GitReference {
+	/// Parse a full reference name, eg. `refs/heads/main`. `None` for anything but branches and
+	/// tags.
+	pub fn parse(full_ref: &str) -> Option<Self> {
+		let heads_regex = Regex::new(r"^refs/heads/(.+)").unwrap();
+		if let Some(captures) = heads_regex.captures(full_ref) {
+			return captures
+				.get(1)
+				.map(|capture| GitReference::Head(capture.as_str().to_owned()));
+		}
+
+		let tags_regex = Regex::new(r"^refs/tags/(.+)").unwrap();
+		if let Some(captures) = tags_regex.captures(full_ref) {
+			return captures
+				.get(1)
+				.map(|capture| GitReference::Tag(capture.as_str().to_owned()));
+		}
+
+		None
+	}
+}
+
 impl<'de> Deserialize<'de> for GitReference {
 	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
 	where
@@ -16,29 +47,102 @@ impl<'de> Deserialize<'de> for GitReference {
 	{
 		let full_ref = String::deserialize(deserializer)?;
 
-		// TODO This could probably be neater?
-		let heads_regex = Regex::new(r"^refs/heads/(.+)").unwrap();
-		let reference = if let Some(captures) = heads_regex.captures(&full_ref) {
-			match captures.get(1) {
-				Some(capture) => Some(GitReference::Head(capture.as_str().to_owned())),
-				None => None,
-			}
-		} else {
-			let tags_regex = Regex::new(r"^refs/tags/(.+)").unwrap();
-			if let Some(captures) = tags_regex.captures(&full_ref) {
-				match captures.get(1) {
-					Some(capture) => Some(GitReference::Tag(capture.as_str().to_owned())),
-					None => None,
-				}
-			} else {
-				None
-			}
-		};
-
 		// TODO probably make sure the ref is valid and not some random shit
-		match reference {
+		match GitReference::parse(&full_ref) {
 			Some(reference) => Ok(reference),
 			None => Err(Error::custom("Invalid ref")),
 		}
 	}
 }
+
+/// A reference updated by a push, from a line passed to a post-receive hook.
+#[derive(Debug, Clone)]
+pub struct GitRefUpdate {
+	pub before: String,
+	pub after: String,
+	/// Full name of the reference, eg. `refs/heads/main`
+	pub refname: String,
+}
+
+impl GitRefUpdate {
+	/// Parse a line of `<oldrev> <newrev> <refname>`
+	fn parse(line: &str) -> Option<Self> {
+		let mut parts = line.split_whitespace();
+		let update = GitRefUpdate {
+			before: parts.next()?.to_owned(),
+			after: parts.next()?.to_owned(),
+			refname: parts.next()?.to_owned(),
+		};
+
+		match parts.next() {
+			Some(_) => None,
+			None => Some(update),
+		}
+	}
+
+	pub fn reference(&self) -> Option<GitReference> {
+		GitReference::parse(&self.refname)
+	}
+
+	pub fn push_type(&self) -> PushType {
+		PushType::detect(&self.before, &self.after, false)
+	}
+}
+
+/// The lines a post-receive hook reads from stdin, one per updated reference.
+#[derive(Debug, Clone)]
+pub struct GitHookPayload(pub Vec<GitRefUpdate>);
+
+const LIMIT: u64 = 1048576; // 1MB
+
+impl FromDataSimple for GitHookPayload {
+	type Error = String;
+
+	fn from_data(_request: &Request, data: Data) -> data::Outcome<Self, String> {
+		let mut body = String::new();
+		if let Err(error) = data.open().take(LIMIT).read_to_string(&mut body) {
+			return Outcome::Failure((Status::BadRequest, format!("{}", error)));
+		}
+
+		let mut updates = vec![];
+		for line in body.lines().filter(|line| !line.trim().is_empty()) {
+			match GitRefUpdate::parse(line) {
+				Some(update) => updates.push(update),
+				None => {
+					return Outcome::Failure((
+						Status::BadRequest,
+						format!(
+							"Invalid line `{}`. Expected `<oldrev> <newrev> <refname>`",
+							line
+						),
+					))
+				}
+			}
+		}
+
+		Outcome::Success(GitHookPayload(updates))
+	}
+}
+
+impl From<&GitRefUpdate> for ArbitraryData {
+	fn from(update: &GitRefUpdate) -> ArbitraryData {
+		let mut data: HashMap<String, String> = HashMap::new();
+		data.insert(
+			"LITTLECI_GIT_EVENT".into(),
+			update.push_type().as_str().into(),
+		);
+		data.insert("LITTLECI_GIT_BEFORE".into(), update.before.clone());
+		data.insert("LITTLECI_GIT_AFTER".into(), update.after.clone());
+
+		match update.reference() {
+			Some(GitReference::Head(branch)) => {
+				data.insert("LITTLECI_GIT_BRANCH".into(), branch);
+			}
+			Some(GitReference::Tag(tag)) => {
+				data.insert("LITTLECI_GIT_TAG".into(), tag);
+			}
+			None => {}
+		}
+		ArbitraryData::new(data)
+	}
+}
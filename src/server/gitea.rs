@@ -0,0 +1,233 @@
+use rocket::data::{self, FromDataSimple};
+use rocket::http::{RawStr, Status};
+use rocket::request::Request;
+use rocket::{Data, Outcome, State};
+use serde::{self, Deserialize};
+use std::collections::{BTreeSet, HashMap};
+use std::io::Read;
+use std::str;
+
+use crate::config::SignatureAlgorithm;
+use crate::model::repositories::Repositories;
+use crate::queue::triggers::PushType;
+use crate::queue::ArbitraryData;
+use crate::server::git::GitReference;
+use crate::server::github::{GitHubCommit, GitHubPullRequest, GitHubPullRequestPayload};
+use crate::server::signature::verify_hmac;
+use crate::server::{AppState, SecretKeyError, NOTIFY_ROUTE_SLUG_INDEX};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+/// Payload of `push` events. Like GitHub's, but Gitea reports the total number of commits.
+#[derive(Deserialize, Debug, Clone)]
+pub struct GiteaPushPayload {
+	#[serde(rename = "ref")]
+	pub reference: GitReference,
+	pub before: String,
+	pub after: String,
+	/// Missing when a branch is deleted
+	pub head_commit: Option<GitHubCommit>,
+	/// Pushed commits. Gitea includes at most `FeedMaxCommitNum`, 5 by default.
+	#[serde(default)]
+	pub commits: Vec<GitHubCommit>,
+	#[serde(default)]
+	pub total_commits: usize,
+}
+
+impl GiteaPushPayload {
+	/// Gitea doesn't send `created`, `deleted` or `forced`, so check the commits before and after.
+	pub fn push_type(&self) -> PushType {
+		PushType::detect(&self.before, &self.after, false)
+	}
+
+	/// Files added, modified or removed by the pushed commits. `None` if the payload doesn't
+	/// include every commit, in which case the changes are unknown.
+	pub fn changed_files(&self) -> Option<Vec<String>> {
+		if self.commits.is_empty() || self.commits.len() < self.total_commits {
+			return None;
+		}
+
+		let files: BTreeSet<&String> = self
+			.commits
+			.iter()
+			.flat_map(|c| {
+				c.added
+					.iter()
+					.chain(c.modified.iter())
+					.chain(c.removed.iter())
+			})
+			.collect();
+
+		Some(files.into_iter().cloned().collect())
+	}
+}
+
+impl From<GiteaPushPayload> for ArbitraryData {
+	fn from(payload: GiteaPushPayload) -> ArbitraryData {
+		let mut data: HashMap<String, String> = HashMap::new();
+		data.insert(
+			"LITTLECI_GIT_EVENT".into(),
+			payload.push_type().as_str().into(),
+		);
+		if let Some(changed_files) = payload.changed_files() {
+			data.insert(
+				"LITTLECI_GIT_CHANGED_FILES".into(),
+				changed_files.join("\n"),
+			);
+		}
+		data.insert("LITTLECI_GIT_BEFORE".into(), payload.before);
+		data.insert("LITTLECI_GIT_AFTER".into(), payload.after);
+
+		if let Some(commit) = payload.head_commit {
+			data.insert("LITTLECI_GIT_COMMIT_MESSAGE".into(), commit.message);
+		}
+
+		match payload.reference {
+			GitReference::Head(branch) => data.insert("LITTLECI_GIT_BRANCH".into(), branch),
+			GitReference::Tag(tag) => data.insert("LITTLECI_GIT_TAG".into(), tag),
+		};
+		ArbitraryData::new(data)
+	}
+}
+
+/// Pull request actions which queue a job
+const PULL_REQUEST_ACTIONS: [&str; 3] = ["opened", "synchronized", "reopened"];
+
+/// Payload of `create` and `delete` events, sent when a branch or tag is created or deleted
+#[derive(Deserialize, Debug, Clone)]
+pub struct GiteaRefPayload {
+	/// Short name of the branch or tag
+	#[serde(rename = "ref")]
+	pub reference: String,
+	pub ref_type: String,
+	/// Missing for deletions
+	#[serde(default)]
+	pub sha: Option<String>,
+}
+
+impl GiteaRefPayload {
+	pub fn git_reference(&self) -> GitReference {
+		match self.ref_type.as_str() {
+			"tag" => GitReference::Tag(self.reference.clone()),
+			_ => GitReference::Head(self.reference.clone()),
+		}
+	}
+
+	pub fn into_data(self, push_type: PushType) -> ArbitraryData {
+		let mut data: HashMap<String, String> = HashMap::new();
+		data.insert("LITTLECI_GIT_EVENT".into(), push_type.as_str().into());
+		if let Some(sha) = &self.sha {
+			data.insert("LITTLECI_GIT_AFTER".into(), sha.clone());
+		}
+
+		match self.git_reference() {
+			GitReference::Head(branch) => data.insert("LITTLECI_GIT_BRANCH".into(), branch),
+			GitReference::Tag(tag) => data.insert("LITTLECI_GIT_TAG".into(), tag),
+		};
+		ArbitraryData::new(data)
+	}
+}
+
+/// Payload of `pull_request` events. Gitea describes pull requests the same way as GitHub, but
+/// with different action names.
+#[derive(Deserialize, Debug, Clone)]
+pub struct GiteaPullRequestPayload {
+	pub action: String,
+	pub number: u64,
+	pub pull_request: GitHubPullRequest,
+}
+
+impl GiteaPullRequestPayload {
+	/// Whether the action changes the code of the pull request, as opposed to eg. its labels.
+	pub fn is_buildable(&self) -> bool {
+		PULL_REQUEST_ACTIONS.contains(&self.action.as_str())
+	}
+}
+
+/// A webhook delivery, dispatched on the `X-Gitea-Event` header, which Forgejo also sends.
+#[derive(Debug, Clone)]
+pub enum GiteaEvent {
+	Push(GiteaPushPayload),
+	/// Tags are queued from `create` events and deletions from `delete` events, so `push`
+	/// events for them are skipped
+	Create(GiteaRefPayload),
+	Delete(GiteaRefPayload),
+	PullRequest(GiteaPullRequestPayload),
+	/// Events which don't queue jobs
+	Unsupported(String),
+}
+
+impl GiteaEvent {
+	fn parse(event: &str, payload: &[u8]) -> Result<Self, serde_json::Error> {
+		match event {
+			"push" => serde_json::from_slice(payload).map(GiteaEvent::Push),
+			"create" => serde_json::from_slice(payload).map(GiteaEvent::Create),
+			"delete" => serde_json::from_slice(payload).map(GiteaEvent::Delete),
+			"pull_request" => serde_json::from_slice(payload).map(GiteaEvent::PullRequest),
+			event => Ok(GiteaEvent::Unsupported(event.to_owned())),
+		}
+	}
+}
+
+const LIMIT: u64 = 26214400; // 25MB
+
+impl FromDataSimple for GiteaEvent {
+	type Error = SecretKeyError;
+
+	fn from_data(request: &Request, data: Data) -> data::Outcome<Self, SecretKeyError> {
+		let signature = match request.headers().get_one("x-gitea-signature") {
+			Some(signature) => signature,
+			None => return Outcome::Failure((Status::BadRequest, SecretKeyError::Missing)),
+		};
+
+		let event = match request.headers().get_one("x-gitea-event") {
+			Some(event) => event,
+			None => return Outcome::Failure((Status::BadRequest, SecretKeyError::BadData)),
+		};
+
+		let signature = match hex::decode(signature) {
+			Ok(signature) => signature,
+			Err(_) => return Outcome::Failure((Status::BadRequest, SecretKeyError::Invalid)),
+		};
+
+		let repository_slug = request
+			.get_param(NOTIFY_ROUTE_SLUG_INDEX)
+			.and_then(|r: Result<&RawStr, _>| r.ok())
+			.expect("Invalid route")
+			.as_str();
+
+		let state = request.guard::<State<AppState>>().unwrap();
+		let repository =
+			Repositories::new(state.connection_manager.clone()).find_by_slug(repository_slug);
+		let repository = match repository {
+			Some(repository) => repository,
+			None => return Outcome::Failure((Status::NotFound, SecretKeyError::NotFound)),
+		};
+
+		let mut payload = Vec::new();
+		if let Err(_) = data.open().take(LIMIT).read_to_end(&mut payload) {
+			return Outcome::Failure((Status::BadRequest, SecretKeyError::BadData));
+		}
+
+		let secret = repository.secret.as_bytes();
+		if !verify_hmac(SignatureAlgorithm::Sha256, secret, &signature, &payload) {
+			return Outcome::Failure((Status::BadRequest, SecretKeyError::Invalid));
+		}
+
+		match GiteaEvent::parse(event, &payload) {
+			Ok(event) => Outcome::Success(event),
+			Err(_) => Outcome::Failure((Status::BadRequest, SecretKeyError::BadData)),
+		}
+	}
+}
+
+impl From<GiteaPullRequestPayload> for ArbitraryData {
+	fn from(payload: GiteaPullRequestPayload) -> ArbitraryData {
+		ArbitraryData::from(GitHubPullRequestPayload {
+			action: payload.action,
+			number: payload.number,
+			pull_request: payload.pull_request,
+		})
+	}
+}
@@ -1,74 +1,224 @@
-use hmac::{Hmac, Mac};
 use rocket::data::{self, FromDataSimple};
-use rocket::http::Status;
+use rocket::http::{RawStr, Status};
 use rocket::request::Request;
 use rocket::{Data, Outcome, State};
 use serde::{self, Deserialize};
-use sha1::Sha1;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::io::Read;
 use std::str;
 
+use crate::config::SignatureAlgorithm;
+use crate::model::repositories::Repositories;
+use crate::queue::triggers::PushType;
 use crate::queue::ArbitraryData;
 use crate::server::git::GitReference;
-use crate::server::{AppState, SecretKeyError};
+use crate::server::signature::verify_hmac;
+use crate::server::{AppState, SecretKeyError, NOTIFY_ROUTE_SLUG_INDEX};
 
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct GitHubCommit {
+	pub id: String,
+	pub message: String,
+	#[serde(default)]
+	pub added: Vec<String>,
+	#[serde(default)]
+	pub modified: Vec<String>,
+	#[serde(default)]
+	pub removed: Vec<String>,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct GitHubPayload {
 	#[serde(rename = "ref")]
 	pub reference: GitReference,
 	pub before: String,
 	pub after: String,
+	/// Missing when a branch is deleted
+	pub head_commit: Option<GitHubCommit>,
+	/// Pushed commits. GitHub includes at most 20.
+	#[serde(default)]
+	pub commits: Vec<GitHubCommit>,
+	#[serde(default)]
+	pub created: bool,
+	#[serde(default)]
+	pub deleted: bool,
+	#[serde(default)]
+	pub forced: bool,
+}
+
+impl GitHubPayload {
+	/// Falls back to the commits before and after to tell force pushes apart.
+	pub fn push_type(&self) -> PushType {
+		if self.deleted {
+			PushType::Delete
+		} else if self.created {
+			PushType::Create
+		} else {
+			PushType::detect(&self.before, &self.after, self.forced)
+		}
+	}
+
+	/// Files added, modified or removed by the pushed commits. `None` if the payload may not
+	/// include every commit, in which case the changes are unknown.
+	pub fn changed_files(&self) -> Option<Vec<String>> {
+		if self.commits.is_empty() || self.commits.len() >= COMMITS_LIMIT {
+			return None;
+		}
+
+		let files: BTreeSet<&String> = self
+			.commits
+			.iter()
+			.flat_map(|c| c.added.iter().chain(c.modified.iter()).chain(c.removed.iter()))
+			.collect();
+
+		Some(files.into_iter().cloned().collect())
+	}
+}
+
+/// Pull request actions which queue a job
+const PULL_REQUEST_ACTIONS: [&str; 3] = ["opened", "synchronize", "reopened"];
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GitHubRepository {
+	pub full_name: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GitHubUser {
+	pub login: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GitHubPullRequestRef {
+	#[serde(rename = "ref")]
+	pub reference: String,
+	pub sha: String,
+	/// Missing when the repository has been deleted, eg. a deleted fork
+	pub repo: Option<GitHubRepository>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GitHubPullRequest {
+	pub head: GitHubPullRequestRef,
+	pub base: GitHubPullRequestRef,
+	pub user: GitHubUser,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GitHubPullRequestPayload {
+	pub action: String,
+	pub number: u64,
+	pub pull_request: GitHubPullRequest,
+}
+
+impl GitHubPullRequestPayload {
+	/// Whether the action changes the code of the pull request, as opposed to eg. its labels.
+	pub fn is_buildable(&self) -> bool {
+		PULL_REQUEST_ACTIONS.contains(&self.action.as_str())
+	}
+
+	/// Whether the head branch is in a different repository to the base branch
+	pub fn is_fork(&self) -> bool {
+		match (&self.pull_request.head.repo, &self.pull_request.base.repo) {
+			(Some(head), Some(base)) => head.full_name != base.full_name,
+			_ => true,
+		}
+	}
+}
+
+/// A webhook delivery, dispatched on the `X-GitHub-Event` header. Deliveries without the header
+/// are treated as pushes.
+#[derive(Debug, Clone)]
+pub enum GitHubEvent {
+	Push(GitHubPayload),
+	PullRequest(GitHubPullRequestPayload),
+	Ping,
+	/// Events which don't queue jobs
+	Unsupported(String),
+}
+
+impl GitHubEvent {
+	fn parse(event: Option<&str>, payload: &[u8]) -> Result<Self, serde_json::Error> {
+		match event {
+			None | Some("push") => serde_json::from_slice(payload).map(GitHubEvent::Push),
+			Some("pull_request") => serde_json::from_slice(payload).map(GitHubEvent::PullRequest),
+			Some("ping") => Ok(GitHubEvent::Ping),
+			Some(event) => Ok(GitHubEvent::Unsupported(event.to_owned())),
+		}
+	}
 }
 
 const LIMIT: u64 = 26214400; // 25MB
 
-type HmacSha1 = Hmac<Sha1>;
+/// Maximum number of commits GitHub includes in a push payload
+const COMMITS_LIMIT: usize = 20;
+
+/// Signature headers in order of preference, with the prefix of their values
+const SIGNATURE_HEADERS: [(&str, &str, SignatureAlgorithm); 2] = [
+	("x-hub-signature-256", "sha256=", SignatureAlgorithm::Sha256),
+	("x-hub-signature", "sha1=", SignatureAlgorithm::Sha1),
+];
 
-impl FromDataSimple for GitHubPayload {
+impl FromDataSimple for GitHubEvent {
 	type Error = SecretKeyError;
 
 	fn from_data(request: &Request, data: Data) -> data::Outcome<Self, SecretKeyError> {
-		let signature = request.headers().get("x-hub-signature").next();
+		let event = request.headers().get_one("x-github-event");
 
-		if signature.is_none() {
-			return Outcome::Failure((Status::BadRequest, SecretKeyError::Missing));
+		let signature = SIGNATURE_HEADERS.iter().find_map(|(header, prefix, algorithm)| {
+			request
+				.headers()
+				.get_one(header)
+				.map(|signature| (*header, *prefix, *algorithm, signature))
+		});
+		let (header, prefix, algorithm, signature) = match signature {
+			Some(signature) => signature,
+			None => return Outcome::Failure((Status::BadRequest, SecretKeyError::Missing)),
+		};
+
+		if !signature.starts_with(prefix) {
+			let used = signature.split('=').next().unwrap_or_default();
+			return Outcome::Failure((
+				Status::BadRequest,
+				SecretKeyError::AlgorithmMismatch(header.into(), prefix.into(), used.into()),
+			));
 		}
 
-		let signature = signature.unwrap();
-		let signature = &signature[5..];
+		let signature = match hex::decode(&signature[prefix.len()..]) {
+			Ok(signature) => signature,
+			Err(_) => return Outcome::Failure((Status::BadRequest, SecretKeyError::Invalid)),
+		};
+
+		let repository_slug = request
+			.get_param(NOTIFY_ROUTE_SLUG_INDEX)
+			.and_then(|r: Result<&RawStr, _>| r.ok())
+			.expect("Invalid route")
+			.as_str();
+
 		let state = request.guard::<State<AppState>>().unwrap();
+		let repository =
+			Repositories::new(state.connection_manager.clone()).find_by_slug(repository_slug);
+		let repository = match repository {
+			Some(repository) => repository,
+			None => return Outcome::Failure((Status::NotFound, SecretKeyError::NotFound)),
+		};
 
 		let mut payload = Vec::new();
 		if let Err(_) = data.open().take(LIMIT).read_to_end(&mut payload) {
 			return Outcome::Failure((Status::BadRequest, SecretKeyError::BadData));
 		}
 
-		if let Ok(mut mac) = HmacSha1::new_varkey(state.config.secret.unsecure()) {
-			mac.input(&payload);
-
-			let signature = hex::decode(&signature);
-			match signature {
-				Ok(signature) => {
-					if mac.verify(&signature).is_ok() {
-						let payload = serde_json::from_slice(&payload);
-						match payload {
-							Ok(payload) => Outcome::Success(payload),
-							Err(_) => {
-								Outcome::Failure((Status::BadRequest, SecretKeyError::Invalid))
-							}
-						}
-					} else {
-						Outcome::Failure((Status::BadRequest, SecretKeyError::Invalid))
-					}
-				}
-				Err(_) => Outcome::Failure((Status::BadRequest, SecretKeyError::Invalid)),
-			}
-		} else {
-			Outcome::Failure((Status::InternalServerError, SecretKeyError::Unknown))
+		// Each repository has its own secret, so a leaked secret only affects one repository
+		if !verify_hmac(algorithm, repository.secret.as_bytes(), &signature, &payload) {
+			return Outcome::Failure((Status::BadRequest, SecretKeyError::Invalid));
+		}
+
+		match GitHubEvent::parse(event, &payload) {
+			Ok(event) => Outcome::Success(event),
+			Err(_) => Outcome::Failure((Status::BadRequest, SecretKeyError::BadData)),
 		}
 	}
 }
@@ -76,9 +226,17 @@ impl FromDataSimple for GitHubPayload {
 impl From<GitHubPayload> for ArbitraryData {
 	fn from(payload: GitHubPayload) -> ArbitraryData {
 		let mut data: HashMap<String, String> = HashMap::new();
+		data.insert("LITTLECI_GIT_EVENT".into(), payload.push_type().as_str().into());
+		if let Some(changed_files) = payload.changed_files() {
+			data.insert("LITTLECI_GIT_CHANGED_FILES".into(), changed_files.join("\n"));
+		}
 		data.insert("LITTLECI_GIT_BEFORE".into(), payload.before);
 		data.insert("LITTLECI_GIT_AFTER".into(), payload.after);
 
+		if let Some(commit) = payload.head_commit {
+			data.insert("LITTLECI_GIT_COMMIT_MESSAGE".into(), commit.message);
+		}
+
 		match payload.reference {
 			GitReference::Head(branch) => data.insert("LITTLECI_GIT_BRANCH".into(), branch),
 			GitReference::Tag(tag) => data.insert("LITTLECI_GIT_TAG".into(), tag),
@@ -86,3 +244,23 @@ impl From<GitHubPayload> for ArbitraryData {
 		ArbitraryData::new(data)
 	}
 }
+
+impl From<GitHubPullRequestPayload> for ArbitraryData {
+	fn from(payload: GitHubPullRequestPayload) -> ArbitraryData {
+		let mut data: HashMap<String, String> = HashMap::new();
+		let fork = payload.is_fork();
+		let pull_request = payload.pull_request;
+
+		data.insert("LITTLECI_GIT_EVENT".into(), "pull_request".into());
+		data.insert("LITTLECI_PR_NUMBER".into(), payload.number.to_string());
+		data.insert("LITTLECI_PR_AUTHOR".into(), pull_request.user.login);
+		data.insert("LITTLECI_PR_FORK".into(), fork.to_string());
+		data.insert("LITTLECI_PR_BASE_REF".into(), pull_request.base.reference);
+		data.insert("LITTLECI_PR_BASE_SHA".into(), pull_request.base.sha);
+		data.insert("LITTLECI_PR_HEAD_REF".into(), pull_request.head.reference);
+		// The commit to build, as for pushes
+		data.insert("LITTLECI_GIT_AFTER".into(), pull_request.head.sha.clone());
+		data.insert("LITTLECI_PR_HEAD_SHA".into(), pull_request.head.sha);
+		ArbitraryData::new(data)
+	}
+}
@@ -0,0 +1,238 @@
+use rocket::data::{self, FromDataSimple};
+use rocket::http::{RawStr, Status};
+use rocket::request::Request;
+use rocket::{Data, Outcome, State};
+use serde::{self, Deserialize};
+use std::collections::{BTreeSet, HashMap};
+use std::io::Read;
+use std::str;
+
+use crate::model::repositories::Repositories;
+use crate::queue::triggers::PushType;
+use crate::queue::ArbitraryData;
+use crate::server::git::GitReference;
+use crate::server::{secret_key_is_valid, AppState, SecretKeyError, NOTIFY_ROUTE_SLUG_INDEX};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GitLabCommit {
+	pub id: String,
+	pub message: String,
+	#[serde(default)]
+	pub added: Vec<String>,
+	#[serde(default)]
+	pub modified: Vec<String>,
+	#[serde(default)]
+	pub removed: Vec<String>,
+}
+
+/// Payload of Push Hook and Tag Push Hook events
+#[derive(Deserialize, Debug, Clone)]
+pub struct GitLabPushPayload {
+	#[serde(rename = "ref")]
+	pub reference: GitReference,
+	pub before: String,
+	pub after: String,
+	/// Missing when a branch or tag is deleted
+	pub checkout_sha: Option<String>,
+	/// Pushed commits. GitLab includes at most 20.
+	#[serde(default)]
+	pub commits: Vec<GitLabCommit>,
+	#[serde(default)]
+	pub total_commits_count: usize,
+}
+
+impl GitLabPushPayload {
+	pub fn push_type(&self) -> PushType {
+		PushType::detect(&self.before, &self.after, false)
+	}
+
+	/// Files added, modified or removed by the pushed commits. `None` if the payload doesn't
+	/// include every commit, in which case the changes are unknown.
+	pub fn changed_files(&self) -> Option<Vec<String>> {
+		if self.commits.is_empty() || self.commits.len() < self.total_commits_count {
+			return None;
+		}
+
+		let files: BTreeSet<&String> = self
+			.commits
+			.iter()
+			.flat_map(|c| c.added.iter().chain(c.modified.iter()).chain(c.removed.iter()))
+			.collect();
+
+		Some(files.into_iter().cloned().collect())
+	}
+}
+
+/// Merge request actions which queue a job
+const MERGE_REQUEST_ACTIONS: [&str; 3] = ["open", "reopen", "update"];
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GitLabUser {
+	pub username: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GitLabLastCommit {
+	pub id: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GitLabMergeRequest {
+	pub iid: u64,
+	/// Missing for merge requests created before GitLab reported actions
+	pub action: Option<String>,
+	pub source_branch: String,
+	pub target_branch: String,
+	pub source_project_id: u64,
+	pub target_project_id: u64,
+	pub last_commit: GitLabLastCommit,
+	/// Set when an update pushed new commits
+	pub oldrev: Option<String>,
+}
+
+/// Payload of Merge Request Hook events
+#[derive(Deserialize, Debug, Clone)]
+pub struct GitLabMergeRequestPayload {
+	pub user: GitLabUser,
+	pub object_attributes: GitLabMergeRequest,
+}
+
+impl GitLabMergeRequestPayload {
+	/// Whether the action changes the code of the merge request. Updates which don't push new
+	/// commits, eg. changing the title, don't.
+	pub fn is_buildable(&self) -> bool {
+		let attributes = &self.object_attributes;
+		match attributes.action.as_ref().map(String::as_str) {
+			Some("update") => attributes.oldrev.is_some(),
+			Some(action) => MERGE_REQUEST_ACTIONS.contains(&action),
+			None => false,
+		}
+	}
+
+	pub fn action(&self) -> &str {
+		self.object_attributes
+			.action
+			.as_ref()
+			.map(String::as_str)
+			.unwrap_or("unknown")
+	}
+}
+
+/// A webhook delivery, dispatched on the `X-Gitlab-Event` header.
+#[derive(Debug, Clone)]
+pub enum GitLabEvent {
+	/// Push Hook or Tag Push Hook
+	Push(GitLabPushPayload),
+	MergeRequest(GitLabMergeRequestPayload),
+	/// Events which don't queue jobs
+	Unsupported(String),
+}
+
+impl GitLabEvent {
+	fn parse(event: &str, payload: &[u8]) -> Result<Self, serde_json::Error> {
+		match event {
+			"Push Hook" | "Tag Push Hook" => {
+				serde_json::from_slice(payload).map(GitLabEvent::Push)
+			}
+			"Merge Request Hook" => {
+				serde_json::from_slice(payload).map(GitLabEvent::MergeRequest)
+			}
+			event => Ok(GitLabEvent::Unsupported(event.to_owned())),
+		}
+	}
+}
+
+const LIMIT: u64 = 26214400; // 25MB
+
+impl FromDataSimple for GitLabEvent {
+	type Error = SecretKeyError;
+
+	fn from_data(request: &Request, data: Data) -> data::Outcome<Self, SecretKeyError> {
+		let token = match request.headers().get_one("x-gitlab-token") {
+			Some(token) => token,
+			None => return Outcome::Failure((Status::BadRequest, SecretKeyError::Missing)),
+		};
+
+		let event = match request.headers().get_one("x-gitlab-event") {
+			Some(event) => event,
+			None => return Outcome::Failure((Status::BadRequest, SecretKeyError::BadData)),
+		};
+
+		let repository_slug = request
+			.get_param(NOTIFY_ROUTE_SLUG_INDEX)
+			.and_then(|r: Result<&RawStr, _>| r.ok())
+			.expect("Invalid route")
+			.as_str();
+
+		let state = request.guard::<State<AppState>>().unwrap();
+		let repository =
+			Repositories::new(state.connection_manager.clone()).find_by_slug(repository_slug);
+		let repository = match repository {
+			Some(repository) => repository,
+			None => return Outcome::Failure((Status::NotFound, SecretKeyError::NotFound)),
+		};
+
+		if !secret_key_is_valid(token, &repository) {
+			return Outcome::Failure((Status::BadRequest, SecretKeyError::Invalid));
+		}
+
+		let mut payload = Vec::new();
+		if let Err(_) = data.open().take(LIMIT).read_to_end(&mut payload) {
+			return Outcome::Failure((Status::BadRequest, SecretKeyError::BadData));
+		}
+
+		match GitLabEvent::parse(event, &payload) {
+			Ok(event) => Outcome::Success(event),
+			Err(_) => Outcome::Failure((Status::BadRequest, SecretKeyError::BadData)),
+		}
+	}
+}
+
+impl From<GitLabPushPayload> for ArbitraryData {
+	fn from(payload: GitLabPushPayload) -> ArbitraryData {
+		let mut data: HashMap<String, String> = HashMap::new();
+		data.insert("LITTLECI_GIT_EVENT".into(), payload.push_type().as_str().into());
+		if let Some(changed_files) = payload.changed_files() {
+			data.insert("LITTLECI_GIT_CHANGED_FILES".into(), changed_files.join("\n"));
+		}
+		data.insert("LITTLECI_GIT_BEFORE".into(), payload.before);
+
+		let checkout_sha = payload.checkout_sha;
+		let head_commit = payload
+			.commits
+			.into_iter()
+			.find(|c| Some(&c.id) == checkout_sha.as_ref());
+		if let Some(commit) = head_commit {
+			data.insert("LITTLECI_GIT_COMMIT_MESSAGE".into(), commit.message);
+		}
+		data.insert("LITTLECI_GIT_AFTER".into(), payload.after);
+
+		match payload.reference {
+			GitReference::Head(branch) => data.insert("LITTLECI_GIT_BRANCH".into(), branch),
+			GitReference::Tag(tag) => data.insert("LITTLECI_GIT_TAG".into(), tag),
+		};
+		ArbitraryData::new(data)
+	}
+}
+
+impl From<GitLabMergeRequestPayload> for ArbitraryData {
+	fn from(payload: GitLabMergeRequestPayload) -> ArbitraryData {
+		let mut data: HashMap<String, String> = HashMap::new();
+		let merge_request = payload.object_attributes;
+		let fork = merge_request.source_project_id != merge_request.target_project_id;
+
+		data.insert("LITTLECI_GIT_EVENT".into(), "pull_request".into());
+		data.insert("LITTLECI_PR_NUMBER".into(), merge_request.iid.to_string());
+		data.insert("LITTLECI_PR_AUTHOR".into(), payload.user.username);
+		data.insert("LITTLECI_PR_FORK".into(), fork.to_string());
+		data.insert("LITTLECI_PR_BASE_REF".into(), merge_request.target_branch);
+		data.insert("LITTLECI_PR_HEAD_REF".into(), merge_request.source_branch);
+		// The commit to build, as for pushes
+		data.insert("LITTLECI_GIT_AFTER".into(), merge_request.last_commit.id.clone());
+		data.insert("LITTLECI_PR_HEAD_SHA".into(), merge_request.last_commit.id);
+		ArbitraryData::new(data)
+	}
+}
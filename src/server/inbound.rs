@@ -0,0 +1,140 @@
+use rocket::data::{self, FromDataSimple};
+use rocket::http::{RawStr, Status};
+use rocket::request::Request;
+use rocket::{Data, Outcome, State};
+use serde_json::Value;
+use std::io::Read;
+
+use crate::config::{InboundSignature, SignatureEncoding};
+use crate::model::repositories::Repositories;
+use crate::server::signature::verify_hmac;
+use crate::server::{AppState, SecretKey, SecretKeyError, NOTIFY_ROUTE_SLUG_INDEX};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+/// Any JSON sent to the generic webhook. Deliveries are authenticated with the repository's
+/// signature settings, or with the repository secret like other notifications.
+#[derive(Debug, Clone)]
+pub struct InboundPayload(pub Value);
+
+const LIMIT: u64 = 26214400; // 25MB
+
+fn verify_signature(
+	settings: &InboundSignature,
+	key: &[u8],
+	signature: &str,
+	body: &[u8],
+) -> bool {
+	let signature = match &settings.prefix {
+		Some(prefix) if signature.starts_with(prefix.as_str()) => &signature[prefix.len()..],
+		Some(_) => return false,
+		None => signature,
+	};
+
+	let signature = match settings.encoding {
+		SignatureEncoding::Hex => hex::decode(signature).ok(),
+		SignatureEncoding::Base64 => base64::decode(signature).ok(),
+	};
+	let signature = match signature {
+		Some(signature) => signature,
+		None => return false,
+	};
+
+	verify_hmac(settings.algorithm, key, &signature, body)
+}
+
+impl FromDataSimple for InboundPayload {
+	type Error = SecretKeyError;
+
+	fn from_data(request: &Request, data: Data) -> data::Outcome<Self, SecretKeyError> {
+		let repository_slug = request
+			.get_param(NOTIFY_ROUTE_SLUG_INDEX)
+			.and_then(|r: Result<&RawStr, _>| r.ok())
+			.expect("Invalid route")
+			.as_str();
+
+		let state = request.guard::<State<AppState>>().unwrap();
+		let repository =
+			Repositories::new(state.connection_manager.clone()).find_by_slug(repository_slug);
+		let repository = match repository {
+			Some(repository) => repository,
+			None => return Outcome::Failure((Status::NotFound, SecretKeyError::NotFound)),
+		};
+
+		let signature_settings = repository
+			.inbound
+			.as_ref()
+			.and_then(|inbound| inbound.signature.as_ref());
+
+		// Without signature settings, require the repository secret like other notifications
+		if signature_settings.is_none() {
+			if let Outcome::Failure(failure) = request.guard::<SecretKey>() {
+				return Outcome::Failure(failure);
+			}
+		}
+
+		let mut body = Vec::new();
+		if let Err(_) = data.open().take(LIMIT).read_to_end(&mut body) {
+			return Outcome::Failure((Status::BadRequest, SecretKeyError::BadData));
+		}
+
+		if let Some(settings) = signature_settings {
+			let signature = match request.headers().get_one(&settings.header) {
+				Some(signature) => signature,
+				None => return Outcome::Failure((Status::BadRequest, SecretKeyError::Missing)),
+			};
+
+			if !verify_signature(settings, repository.secret.as_bytes(), signature, &body) {
+				return Outcome::Failure((Status::BadRequest, SecretKeyError::Invalid));
+			}
+		}
+
+		match serde_json::from_slice(&body) {
+			Ok(payload) => Outcome::Success(InboundPayload(payload)),
+			Err(_) => Outcome::Failure((Status::BadRequest, SecretKeyError::BadData)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const BODY: &[u8] = b"The quick brown fox jumps over the lazy dog";
+	const HEX: &str = "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8";
+	const BASE64: &str = "97yD9DBThCSxMpjmqm+xQ+9NWaFJRhdZl0edvC0aPNg=";
+
+	fn settings(json: &str) -> InboundSignature {
+		serde_json::from_str(json).unwrap()
+	}
+
+	#[test]
+	fn verify_signature_decodes_hex_and_base64() {
+		let hex =
+			settings(r#"{"header": "X-Signature", "algorithm": "sha256", "encoding": "hex"}"#);
+		let base64 =
+			settings(r#"{"header": "X-Signature", "algorithm": "sha256", "encoding": "base64"}"#);
+
+		assert!(verify_signature(&hex, b"key", HEX, BODY));
+		assert!(verify_signature(&base64, b"key", BASE64, BODY));
+		assert!(!verify_signature(&hex, b"key", BASE64, BODY));
+		assert!(!verify_signature(&base64, b"key", HEX, BODY));
+		assert!(!verify_signature(&hex, b"other key", HEX, BODY));
+	}
+
+	#[test]
+	fn verify_signature_requires_and_removes_the_prefix() {
+		let prefixed = settings(
+			r#"{"header": "X-Hub-Signature-256", "algorithm": "sha256", "prefix": "sha256="}"#,
+		);
+		let unprefixed = settings(r#"{"header": "X-Hub-Signature-256", "algorithm": "sha256"}"#);
+		let signature = format!("sha256={}", HEX);
+		let wrong_prefix = format!("sha1={}", HEX);
+
+		assert!(verify_signature(&prefixed, b"key", &signature, BODY));
+		assert!(!verify_signature(&prefixed, b"key", HEX, BODY));
+		assert!(!verify_signature(&prefixed, b"key", &wrong_prefix, BODY));
+		assert!(!verify_signature(&unprefixed, b"key", &signature, BODY));
+	}
+}
@@ -1,8 +1,9 @@
 use base64::encode;
 use failure::{format_err, Error, Fail};
 use rocket::config::{Config, Environment};
-use rocket::http::{Method, RawStr, Status};
+use rocket::http::{ContentType, Method, RawStr, Status};
 use rocket::request::{self, FromRequest, Request};
+use rocket::response::content::Content;
 use rocket::response::status::Custom;
 use rocket::response::Redirect;
 use rocket::{catch, catchers, delete, get, post, put, routes, Outcome, State};
@@ -14,27 +15,43 @@ use std::env;
 use std::fs::read_to_string;
 use std::path::PathBuf;
 
-use crate::config::{GitTrigger, Trigger};
+use crate::model::builds::Builds;
+use crate::model::caches::{Cache, Caches};
 use crate::model::queues::{JobSummary, Queues};
 use crate::model::repositories::{Repositories, Repository};
+use crate::model::test_results::{JobTestResults, TestResultChanges, TestResults};
 use crate::model::users::{UpdateUserPassword, User, Users};
-use crate::queue::{ArbitraryData, QueueItem};
+use crate::queue::cache::CacheManager;
+use crate::queue::inbound::{extract, filter_mismatch};
+use crate::queue::triggers::{data_skip_reason, skip_reason, GitEvent, PushType, RefKind};
+use crate::queue::{ArbitraryData, Build, QueueItem};
 use crate::AppState;
 
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
 
+mod ansi;
 mod auth;
 mod git;
+mod gitea;
 mod github;
+mod gitlab;
+mod inbound;
+mod signature;
 pub mod response;
 mod static_assets;
+mod summary;
 
+use ansi::OutputFormat;
 use auth::{authenticate_user, AuthenticationPayload, UserPayload};
-use git::GitReference;
-use github::GitHubPayload;
+use git::{GitHookPayload, GitReference};
+use gitea::GiteaEvent;
+use github::GitHubEvent;
+use gitlab::GitLabEvent;
+use inbound::InboundPayload;
 use response::{AppConfigResponse, ErrorResponse, RepositoryResponse, Response, UserResponse};
 use static_assets::{AssetType, Assets};
+use summary::SummaryFormat;
 
 pub struct SecretKey;
 
@@ -49,6 +66,28 @@ pub enum SecretKeyError {
 	BadData,
 	#[fail(display = "Unhandled error")]
 	Unknown,
+	#[fail(display = "Repository not found")]
+	NotFound,
+	#[fail(
+		display = "Signature algorithm mismatch. `{}` must start with `{}`, not `{}=`",
+		_0, _1, _2
+	)]
+	AlgorithmMismatch(String, String, String),
+}
+
+impl SecretKeyError {
+	fn status(&self) -> Status {
+		match self {
+			SecretKeyError::NotFound => Status::NotFound,
+			SecretKeyError::Unknown => Status::InternalServerError,
+			_ => Status::BadRequest,
+		}
+	}
+}
+
+/// Respond with the reason a webhook delivery couldn't be verified.
+fn webhook_error(error: SecretKeyError) -> Custom<Json<ErrorResponse>> {
+	Custom(error.status(), Json(ErrorResponse::new(format!("{}", error))))
 }
 
 fn secret_key_is_valid(secret: &str, repository: &Repository) -> bool {
@@ -107,40 +146,103 @@ impl<'a, 'r> FromRequest<'a, 'r> for SecretKey {
 	}
 }
 
+/// Identifies repeated deliveries of a notification, from the `Idempotency-Key` header or the
+/// delivery ID GitHub sends with webhooks.
+pub struct IdempotencyKey(Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for IdempotencyKey {
+	type Error = ();
+
+	fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+		let headers = request.headers();
+		let key = headers
+			.get_one("idempotency-key")
+			.or_else(|| headers.get_one("x-github-delivery"))
+			.filter(|key| !key.is_empty())
+			.map(String::from);
+
+		Outcome::Success(IdempotencyKey(key))
+	}
+}
+
 fn notify_new_job(
 	repository: &str,
 	values: ArbitraryData,
+	idempotency_key: Option<String>,
 	state: &AppState,
 ) -> Result<Response<QueueItem>, String> {
-	match state.queue_manager.push(repository, values) {
+	match state
+		.queue_manager
+		.push(repository, values, idempotency_key)
+	{
 		Ok(item) => Ok(Response { response: item }),
 		Err(error) => Err(format!("{}", error)),
 	}
 }
 
-fn notify_job(
-	repository: &RawStr,
-	values: ArbitraryData,
+/// Queue a job, responding with the job or the reason it couldn't be queued.
+fn queue_job(
+	repository_name: &str,
+	data: ArbitraryData,
+	idempotency_key: Option<String>,
 	state: &AppState,
-) -> Result<Json<Response<QueueItem>>, Custom<Json<ErrorResponse>>> {
-	match notify_new_job(repository.as_str(), values, state) {
-		Ok(job) => Ok(Json(job)),
+) -> Result<Json<JobOrSkipped>, Custom<Json<ErrorResponse>>> {
+	debug!("Notifying new job for repository {}", repository_name);
+	match notify_new_job(repository_name, data, idempotency_key, state) {
+		Ok(response) => Ok(Json(JobOrSkipped::Job(response))),
 		Err(error) => Err(Custom(
 			Status::InternalServerError,
-			Json(ErrorResponse::new(error)),
+			Json(ErrorResponse::new(
+				format!("Unable to add job to the queue. {}", error).into(),
+			)),
 		)),
 	}
 }
 
+/// Queue a job unless the data names a branch, tag or pull request which the repository's
+/// triggers don't match.
+fn notify_job(
+	repository: &RawStr,
+	values: ArbitraryData,
+	idempotency_key: Option<String>,
+	state: &AppState,
+) -> Result<Json<JobOrSkipped>, Custom<Json<ErrorResponse>>> {
+	let repository_name = repository.as_str();
+
+	let repository =
+		Repositories::new(state.connection_manager.clone()).find_by_slug(repository_name);
+	let repository = match repository {
+		Some(repository) => repository,
+		None => {
+			return Err(Custom(
+				Status::NotFound,
+				Json(ErrorResponse::new(
+					format!("Repository `{}` not found", repository_name).into(),
+				)),
+			))
+		}
+	};
+
+	match data_skip_reason(repository_name, &repository.triggers, &values) {
+		Some(reason) => {
+			debug!("Skipping job for repository {}. {}", repository_name, reason);
+			Ok(Json(JobOrSkipped::Skipped(reason)))
+		}
+		None => queue_job(repository_name, values, idempotency_key, state),
+	}
+}
+
 #[get("/notify/<repository>")]
 pub fn notify(
 	repository: &RawStr,
 	_secret_key: SecretKey,
+	idempotency_key: IdempotencyKey,
 	state: State<AppState>,
-) -> Result<Json<Response<QueueItem>>, Custom<Json<ErrorResponse>>> {
+) -> Result<Json<JobOrSkipped>, Custom<Json<ErrorResponse>>> {
 	notify_job(
 		repository,
 		ArbitraryData::new(HashMap::new()),
+		idempotency_key.0,
 		state.inner(),
 	)
 }
@@ -150,9 +252,15 @@ pub fn notify_with_data(
 	repository: &RawStr,
 	data: Json<ArbitraryData>,
 	_secret_key: SecretKey,
+	idempotency_key: IdempotencyKey,
 	state: State<AppState>,
-) -> Result<Json<Response<QueueItem>>, Custom<Json<ErrorResponse>>> {
-	notify_job(repository, data.into_inner(), state.inner())
+) -> Result<Json<JobOrSkipped>, Custom<Json<ErrorResponse>>> {
+	notify_job(
+		repository,
+		data.into_inner(),
+		idempotency_key.0,
+		state.inner(),
+	)
 }
 
 #[derive(Serialize, Clone, Debug)]
@@ -163,14 +271,14 @@ pub enum JobOrSkipped {
 	Job(Response<QueueItem>),
 }
 
-#[post("/notify/<repository>/github", format = "json", data = "<payload>")]
-pub fn notify_github(
-	repository: &RawStr,
-	payload: GitHubPayload,
-	state: State<AppState>,
+/// Queue a job for a push or pull request if it matches any of the repository's triggers.
+fn notify_git_event(
+	repository_name: &str,
+	event: GitEvent,
+	data: ArbitraryData,
+	idempotency_key: Option<String>,
+	state: &AppState,
 ) -> Result<Json<JobOrSkipped>, Custom<Json<ErrorResponse>>> {
-	let repository_name = repository.as_str();
-
 	let repository =
 		Repositories::new(state.connection_manager.clone()).find_by_slug(repository_name);
 	let repository = match repository {
@@ -185,60 +293,282 @@ pub fn notify_github(
 		}
 	};
 
-	let mut should_skip = true;
-	let triggers = repository.triggers.clone();
-	for trigger in triggers.into_iter() {
-		match trigger {
-			Trigger::Any => {
-				debug!("Matched any trigger for repository {}", repository_name);
-				should_skip = false;
-				break;
+	match skip_reason(repository_name, &repository.triggers, &event) {
+		Some(reason) => {
+			debug!("Skipping job for repository {}. {}", repository_name, reason);
+			Ok(Json(JobOrSkipped::Skipped(reason)))
+		}
+		None => queue_job(repository_name, data, idempotency_key, state),
+	}
+}
+
+fn git_reference_event(reference: &GitReference) -> (RefKind, String) {
+	match reference {
+		GitReference::Head(branch) => (RefKind::Head, branch.clone()),
+		GitReference::Tag(tag) => (RefKind::Tag, tag.clone()),
+	}
+}
+
+#[post("/notify/<repository>/github", format = "json", data = "<payload>")]
+pub fn notify_github(
+	repository: &RawStr,
+	payload: Result<GitHubEvent, SecretKeyError>,
+	idempotency_key: IdempotencyKey,
+	state: State<AppState>,
+) -> Result<Json<JobOrSkipped>, Custom<Json<ErrorResponse>>> {
+	let payload = payload.map_err(webhook_error)?;
+	let (kind, name, changed_files, push_type, data) = match payload {
+		GitHubEvent::Push(payload) => {
+			let changed_files = payload.changed_files();
+			let push_type = payload.push_type();
+			let (kind, name) = git_reference_event(&payload.reference);
+			(kind, name, changed_files, push_type, ArbitraryData::from(payload))
+		}
+		GitHubEvent::PullRequest(payload) => {
+			if !payload.is_buildable() {
+				return Ok(Json(JobOrSkipped::Skipped(format!(
+					"Pull request action `{}` doesn't queue jobs",
+					payload.action
+				))));
 			}
-			Trigger::Git(GitTrigger::Any) => {
-				debug!("Matched any git trigger for repository {}", repository_name);
-				should_skip = false;
-				break;
+			let name = payload.pull_request.base.reference.clone();
+			let data = ArbitraryData::from(payload);
+			(RefKind::PullRequest, name, None, PushType::Push, data)
+		}
+		GitHubEvent::Ping => {
+			return Ok(Json(JobOrSkipped::Skipped("Ping received".into())));
+		}
+		GitHubEvent::Unsupported(event) => {
+			return Ok(Json(JobOrSkipped::Skipped(format!(
+				"Event `{}` doesn't queue jobs",
+				event
+			))));
+		}
+	};
+
+	let event = GitEvent {
+		kind,
+		name,
+		changed_files,
+		push_type,
+	};
+	notify_git_event(
+		repository.as_str(),
+		event,
+		data,
+		idempotency_key.0,
+		state.inner(),
+	)
+}
+
+#[post("/notify/<repository>/gitlab", format = "json", data = "<payload>")]
+pub fn notify_gitlab(
+	repository: &RawStr,
+	payload: Result<GitLabEvent, SecretKeyError>,
+	idempotency_key: IdempotencyKey,
+	state: State<AppState>,
+) -> Result<Json<JobOrSkipped>, Custom<Json<ErrorResponse>>> {
+	let payload = payload.map_err(webhook_error)?;
+	let (kind, name, changed_files, push_type, data) = match payload {
+		GitLabEvent::Push(payload) => {
+			let changed_files = payload.changed_files();
+			let push_type = payload.push_type();
+			let (kind, name) = git_reference_event(&payload.reference);
+			(kind, name, changed_files, push_type, ArbitraryData::from(payload))
+		}
+		GitLabEvent::MergeRequest(payload) => {
+			if !payload.is_buildable() {
+				return Ok(Json(JobOrSkipped::Skipped(format!(
+					"Merge request action `{}` doesn't queue jobs",
+					payload.action()
+				))));
 			}
-			Trigger::Git(GitTrigger::Tag) => {
-				debug!("Matched tag trigger");
-				if let GitReference::Tag(_) = &payload.reference {
-					debug!("Matched tag trigger for repository {}", repository_name);
-					should_skip = false;
-				}
+			let name = payload.object_attributes.target_branch.clone();
+			let data = ArbitraryData::from(payload);
+			(RefKind::PullRequest, name, None, PushType::Push, data)
+		}
+		GitLabEvent::Unsupported(event) => {
+			return Ok(Json(JobOrSkipped::Skipped(format!(
+				"Event `{}` doesn't queue jobs",
+				event
+			))));
+		}
+	};
+
+	let event = GitEvent {
+		kind,
+		name,
+		changed_files,
+		push_type,
+	};
+	notify_git_event(
+		repository.as_str(),
+		event,
+		data,
+		idempotency_key.0,
+		state.inner(),
+	)
+}
+
+#[post("/notify/<repository>/gitea", format = "json", data = "<payload>")]
+pub fn notify_gitea(
+	repository: &RawStr,
+	payload: Result<GiteaEvent, SecretKeyError>,
+	idempotency_key: IdempotencyKey,
+	state: State<AppState>,
+) -> Result<Json<JobOrSkipped>, Custom<Json<ErrorResponse>>> {
+	let payload = payload.map_err(webhook_error)?;
+	let (kind, name, changed_files, push_type, data) = match payload {
+		GiteaEvent::Push(payload) => {
+			if let GitReference::Tag(_) = &payload.reference {
+				return Ok(Json(JobOrSkipped::Skipped(
+					"Tags are queued by `create` events".into(),
+				)));
 			}
-			Trigger::Git(GitTrigger::Head(refs)) => {
-				for trigger_ref in refs.iter() {
-					if let GitReference::Head(payload_ref) = &payload.reference {
-						if *trigger_ref == *payload_ref {
-							debug!(
-								"Matched head trigger {} for repository {}",
-								&trigger_ref, repository_name
-							);
-							should_skip = false;
-						}
-					}
-				}
+			if payload.push_type() == PushType::Delete {
+				return Ok(Json(JobOrSkipped::Skipped(
+					"Deletions are queued by `delete` events".into(),
+				)));
+			}
+			let changed_files = payload.changed_files();
+			let push_type = payload.push_type();
+			let (kind, name) = git_reference_event(&payload.reference);
+			(kind, name, changed_files, push_type, ArbitraryData::from(payload))
+		}
+		GiteaEvent::Create(payload) => {
+			let reference = payload.git_reference();
+			if let GitReference::Head(_) = &reference {
+				return Ok(Json(JobOrSkipped::Skipped(
+					"Branches are queued by `push` events".into(),
+				)));
 			}
+			let (kind, name) = git_reference_event(&reference);
+			let data = payload.into_data(PushType::Create);
+			(kind, name, None, PushType::Create, data)
+		}
+		GiteaEvent::Delete(payload) => {
+			let (kind, name) = git_reference_event(&payload.git_reference());
+			let data = payload.into_data(PushType::Delete);
+			(kind, name, None, PushType::Delete, data)
+		}
+		GiteaEvent::PullRequest(payload) => {
+			if !payload.is_buildable() {
+				return Ok(Json(JobOrSkipped::Skipped(format!(
+					"Pull request action `{}` doesn't queue jobs",
+					payload.action
+				))));
+			}
+			let name = payload.pull_request.base.reference.clone();
+			let data = ArbitraryData::from(payload);
+			(RefKind::PullRequest, name, None, PushType::Push, data)
+		}
+		GiteaEvent::Unsupported(event) => {
+			return Ok(Json(JobOrSkipped::Skipped(format!(
+				"Event `{}` doesn't queue jobs",
+				event
+			))));
 		}
+	};
+
+	let event = GitEvent {
+		kind,
+		name,
+		changed_files,
+		push_type,
+	};
+	notify_git_event(
+		repository.as_str(),
+		event,
+		data,
+		idempotency_key.0,
+		state.inner(),
+	)
+}
+
+/// Queue a job for each reference updated by a push to a self-hosted repository, as sent by the
+/// post-receive hook written by `littleci hook install`.
+#[post("/notify/<repository>/git", data = "<payload>")]
+pub fn notify_git(
+	repository: &RawStr,
+	payload: GitHookPayload,
+	_secret_key: SecretKey,
+	idempotency_key: IdempotencyKey,
+	state: State<AppState>,
+) -> Result<Json<Vec<JobOrSkipped>>, Custom<Json<ErrorResponse>>> {
+	let mut responses = vec![];
+	for update in payload.0.iter() {
+		let (kind, name) = match update.reference() {
+			Some(reference) => git_reference_event(&reference),
+			None => {
+				responses.push(JobOrSkipped::Skipped(format!(
+					"`{}` isn't a branch or tag",
+					&update.refname
+				)));
+				continue;
+			}
+		};
+
+		let event = GitEvent {
+			kind,
+			name,
+			changed_files: None,
+			push_type: update.push_type(),
+		};
+		let data = ArbitraryData::from(update);
+		// A push updating several references queues a job for each of them
+		let key = idempotency_key
+			.0
+			.as_ref()
+			.map(|key| format!("{} {}", key, &update.refname));
+		let Json(response) =
+			notify_git_event(repository.as_str(), event, data, key, state.inner())?;
+		responses.push(response);
 	}
+	Ok(Json(responses))
+}
 
-	if should_skip {
-		debug!("Skipping job for repository {}", repository_name);
-		Ok(Json(JobOrSkipped::Skipped(
-			"Trigger rules not matched. No job queued".into(),
-		)))
-	} else {
-		debug!("Notifying new job for repository {}", repository_name);
-		match notify_new_job(repository_name, ArbitraryData::from(payload), state.inner()) {
-			Ok(response) => Ok(Json(JobOrSkipped::Job(response))),
-			Err(error) => Err(Custom(
-				Status::InternalServerError,
+#[post("/notify/<repository>/webhook", data = "<payload>")]
+pub fn notify_webhook(
+	repository: &RawStr,
+	payload: Result<InboundPayload, SecretKeyError>,
+	idempotency_key: IdempotencyKey,
+	state: State<AppState>,
+) -> Result<Json<JobOrSkipped>, Custom<Json<ErrorResponse>>> {
+	let payload = payload.map_err(webhook_error)?;
+	let repository_name = repository.as_str();
+
+	let repository =
+		Repositories::new(state.connection_manager.clone()).find_by_slug(repository_name);
+	let repository = match repository {
+		Some(repository) => repository,
+		None => {
+			return Err(Custom(
+				Status::NotFound,
 				Json(ErrorResponse::new(
-					format!("Unable to add job to the queue. {}", error).into(),
+					format!("Repository `{}` not found", repository_name).into(),
 				)),
-			)),
+			))
+		}
+	};
+	let inbound = match repository.inbound {
+		Some(inbound) => inbound,
+		None => {
+			return Err(Custom(
+				Status::BadRequest,
+				Json(ErrorResponse::new(
+					format!("Repository `{}` has no inbound webhook", repository_name).into(),
+				)),
+			))
 		}
+	};
+
+	if let Some(reason) = filter_mismatch(&inbound, &payload.0) {
+		debug!("Skipping job for repository {}. {}", repository_name, reason);
+		return Ok(Json(JobOrSkipped::Skipped(reason)));
 	}
+
+	let data = ArbitraryData::new(extract(&inbound, &payload.0));
+	queue_job(repository_name, data, idempotency_key.0, state.inner())
 }
 
 #[get("/repositories")]
@@ -544,6 +874,130 @@ pub fn delete_repository(
 	}
 }
 
+#[get("/repositories/<repository>/caches")]
+pub fn caches(
+	repository: &RawStr,
+	_auth: AuthenticationPayload,
+	state: State<AppState>,
+) -> Result<Json<Vec<Cache>>, Custom<Json<ErrorResponse>>> {
+	let repository = repository.as_str();
+	let record = Repositories::new(state.connection_manager.clone()).find_by_slug(repository);
+	let repository = match record {
+		Some(repository) => repository,
+		None => {
+			return Err(Custom(
+				Status::NotFound,
+				Json(ErrorResponse::new(
+					format!("Repository `{}` not found", repository).into(),
+				)),
+			))
+		}
+	};
+
+	match Caches::new(state.connection_manager.clone()).all_for_repository(&repository.id) {
+		Ok(caches) => Ok(Json(caches)),
+		Err(error) => {
+			error!("{}", error);
+			Err(Custom(
+				Status::InternalServerError,
+				Json(ErrorResponse::new("Unable to fetch caches.".into())),
+			))
+		}
+	}
+}
+
+#[delete("/repositories/<repository>/caches")]
+pub fn purge_caches(
+	repository: &RawStr,
+	_auth: AuthenticationPayload,
+	state: State<AppState>,
+) -> Result<(), Custom<Json<ErrorResponse>>> {
+	let repository = repository.as_str();
+	let record = Repositories::new(state.connection_manager.clone()).find_by_slug(repository);
+	let repository = match record {
+		Some(repository) => repository,
+		None => {
+			return Err(Custom(
+				Status::NotFound,
+				Json(ErrorResponse::new(
+					format!("Repository `{}` not found", repository).into(),
+				)),
+			))
+		}
+	};
+
+	let cache_manager =
+		CacheManager::new(state.connection_manager.clone(), state.config.clone());
+	match cache_manager.purge_repository(&repository.id) {
+		Ok(()) => Ok(()),
+		Err(error) => {
+			error!("Error purging caches: {}", error);
+			Err(Custom(
+				Status::InternalServerError,
+				Json(ErrorResponse::new("Could not purge caches".into())),
+			))
+		}
+	}
+}
+
+#[delete("/repositories/<repository>/caches/<id>")]
+pub fn purge_cache(
+	repository: &RawStr,
+	id: &RawStr,
+	_auth: AuthenticationPayload,
+	state: State<AppState>,
+) -> Result<(), Custom<Json<ErrorResponse>>> {
+	let repository = repository.as_str();
+	let record = Repositories::new(state.connection_manager.clone()).find_by_slug(repository);
+	let repository = match record {
+		Some(repository) => repository,
+		None => {
+			return Err(Custom(
+				Status::NotFound,
+				Json(ErrorResponse::new(
+					format!("Repository `{}` not found", repository).into(),
+				)),
+			))
+		}
+	};
+
+	let id = id.as_str();
+	let caches = Caches::new(state.connection_manager.clone()).all_for_repository(&repository.id);
+	let cache = caches
+		.unwrap_or_default()
+		.into_iter()
+		.find(|cache| cache.id == id);
+
+	let cache = match cache {
+		Some(cache) => cache,
+		None => {
+			return Err(Custom(
+				Status::NotFound,
+				Json(ErrorResponse::new(
+					format!(
+						"Couldn't find cache `{}` for repository `{}`",
+						id, &repository.slug
+					)
+					.into(),
+				)),
+			))
+		}
+	};
+
+	let cache_manager =
+		CacheManager::new(state.connection_manager.clone(), state.config.clone());
+	match cache_manager.purge(&cache) {
+		Ok(()) => Ok(()),
+		Err(error) => {
+			error!("Error purging cache: {}", error);
+			Err(Custom(
+				Status::InternalServerError,
+				Json(ErrorResponse::new("Could not purge cache".into())),
+			))
+		}
+	}
+}
+
 #[get("/jobs")]
 pub fn all_jobs(
 	_auth: AuthenticationPayload,
@@ -603,13 +1057,14 @@ pub fn jobs(
 	}
 }
 
-#[get("/repositories/<repository>/jobs/<id>/output")]
+#[get("/repositories/<repository>/jobs/<id>/output?<format>")]
 pub fn log_output(
 	repository: &RawStr,
 	id: &RawStr,
+	format: Option<OutputFormat>,
 	_auth: AuthenticationPayload,
 	state: State<AppState>,
-) -> Result<String, Custom<String>> {
+) -> Result<Content<String>, Custom<String>> {
 	let repository = repository.as_str();
 	let record = Repositories::new(state.connection_manager.clone()).find_by_slug(repository);
 	let repository = match record {
@@ -632,8 +1087,13 @@ pub fn log_output(
 				"{}/jobs/{}/output.log",
 				&state.config.data_dir, &job.id
 			));
+			let format = format.unwrap_or_default();
+			let content_type = match format {
+				OutputFormat::Html => ContentType::HTML,
+				_ => ContentType::Plain,
+			};
 			match log_output {
-				Ok(log_output) => Ok(log_output),
+				Ok(log_output) => Ok(Content(content_type, ansi::render(log_output, format))),
 				Err(_) => Err(Custom(
 					Status::InternalServerError,
 					format!("Unable to read output file for job `{}`", &id).into(),
@@ -651,6 +1111,236 @@ pub fn log_output(
 	}
 }
 
+#[get("/repositories/<repository>/jobs/<id>/steps/<step>/output?<format>")]
+pub fn step_output(
+	repository: &RawStr,
+	id: &RawStr,
+	step: &RawStr,
+	format: Option<OutputFormat>,
+	_auth: AuthenticationPayload,
+	state: State<AppState>,
+) -> Result<Content<String>, Custom<String>> {
+	let repository = repository.as_str();
+	let record = Repositories::new(state.connection_manager.clone()).find_by_slug(repository);
+	let repository = match record {
+		Some(repository) => repository,
+		None => {
+			return Err(Custom(
+				Status::NotFound,
+				format!("Repository `{}` does not exist", repository).into(),
+			));
+		}
+	};
+
+	let id = id.as_str();
+	let step = step.as_str();
+
+	let queues_model = Queues::new(state.connection_manager.clone());
+	match queues_model.job(&repository.id, &id) {
+		Ok(job) => {
+			// Only read logs of steps which belong to the job
+			if !job.steps.iter().any(|s| s.name == step) {
+				return Err(Custom(
+					Status::NotFound,
+					format!("Couldn't find step `{}` for job `{}`", step, &id).into(),
+				));
+			}
+
+			let log_output = read_to_string(format!(
+				"{}/jobs/{}/steps/{}.log",
+				&state.config.data_dir, &job.id, step
+			));
+			let format = format.unwrap_or_default();
+			let content_type = match format {
+				OutputFormat::Html => ContentType::HTML,
+				_ => ContentType::Plain,
+			};
+			match log_output {
+				Ok(log_output) => Ok(Content(content_type, ansi::render(log_output, format))),
+				Err(_) => Err(Custom(
+					Status::NotFound,
+					format!("Step `{}` of job `{}` hasn't produced any output", step, &id).into(),
+				)),
+			}
+		}
+		Err(_) => Err(Custom(
+			Status::NotFound,
+			format!(
+				"Couldn't find job `{}` for repository `{}`",
+				&id, &repository.slug
+			)
+			.into(),
+		)),
+	}
+}
+
+#[get("/repositories/<repository>/jobs/<id>/summary?<format>")]
+pub fn job_summary(
+	repository: &RawStr,
+	id: &RawStr,
+	format: Option<SummaryFormat>,
+	_auth: AuthenticationPayload,
+	state: State<AppState>,
+) -> Result<Content<String>, Custom<String>> {
+	let repository = repository.as_str();
+	let record = Repositories::new(state.connection_manager.clone()).find_by_slug(repository);
+	let repository = match record {
+		// We just need the repository slug
+		Some(repository) => repository,
+		None => {
+			return Err(Custom(
+				Status::NotFound,
+				format!("Repository `{}` does not exist", repository).into(),
+			));
+		}
+	};
+
+	let id = id.as_str();
+
+	let queues_model = Queues::new(state.connection_manager.clone());
+	match queues_model.job(&repository.id, &id) {
+		Ok(job) => {
+			let markdown = read_to_string(format!(
+				"{}/jobs/{}/summary.md",
+				&state.config.data_dir, &job.id
+			));
+			let format = format.unwrap_or_default();
+			match markdown {
+				Ok(markdown) => Ok(Content(
+					format.content_type(),
+					summary::render(markdown, format),
+				)),
+				Err(_) => Err(Custom(
+					Status::NotFound,
+					format!("Job `{}` does not have a summary", &id).into(),
+				)),
+			}
+		}
+		Err(_) => Err(Custom(
+			Status::NotFound,
+			format!(
+				"Couldn't find job `{}` for repository `{}`",
+				&id, &repository.slug
+			)
+			.into(),
+		)),
+	}
+}
+
+#[get("/repositories/<repository>/jobs/<id>/tests")]
+pub fn job_test_results(
+	repository: &RawStr,
+	id: &RawStr,
+	_auth: AuthenticationPayload,
+	state: State<AppState>,
+) -> Result<Json<JobTestResults>, Custom<Json<ErrorResponse>>> {
+	let repository = repository.as_str();
+	let record = Repositories::new(state.connection_manager.clone()).find_by_slug(repository);
+	let repository = match record {
+		// We just need the repository slug
+		Some(repository) => repository,
+		None => {
+			return Err(Custom(
+				Status::NotFound,
+				Json(ErrorResponse::new(
+					format!("Repository `{}` does not exist", repository).into(),
+				)),
+			));
+		}
+	};
+
+	let id = id.as_str();
+
+	let queues_model = Queues::new(state.connection_manager.clone());
+	let job = match queues_model.job(&repository.id, &id) {
+		Ok(job) => job,
+		Err(_) => {
+			return Err(Custom(
+				Status::NotFound,
+				Json(ErrorResponse::new(
+					format!(
+						"Couldn't find job `{}` for repository `{}`",
+						&id, &repository.slug
+					)
+					.into(),
+				)),
+			));
+		}
+	};
+
+	match TestResults::new(state.connection_manager.clone()).for_job(&job.id) {
+		Ok(results) => Ok(Json(results)),
+		Err(error) => Err(Custom(
+			Status::InternalServerError,
+			Json(ErrorResponse::new(format!("{}", error))),
+		)),
+	}
+}
+
+/// Number of previous builds on the same branch to look through for flaky tests.
+const FLAKY_TEST_HISTORY: usize = 10;
+
+#[get("/repositories/<repository>/jobs/<id>/tests/changes")]
+pub fn job_test_changes(
+	repository: &RawStr,
+	id: &RawStr,
+	_auth: AuthenticationPayload,
+	state: State<AppState>,
+) -> Result<Json<TestResultChanges>, Custom<Json<ErrorResponse>>> {
+	let repository = repository.as_str();
+	let record = Repositories::new(state.connection_manager.clone()).find_by_slug(repository);
+	let repository = match record {
+		// We just need the repository slug
+		Some(repository) => repository,
+		None => {
+			return Err(Custom(
+				Status::NotFound,
+				Json(ErrorResponse::new(
+					format!("Repository `{}` does not exist", repository).into(),
+				)),
+			));
+		}
+	};
+
+	let id = id.as_str();
+
+	let queues_model = Queues::new(state.connection_manager.clone());
+	let job = match queues_model.job(&repository.id, &id) {
+		Ok(job) => job,
+		Err(_) => {
+			return Err(Custom(
+				Status::NotFound,
+				Json(ErrorResponse::new(
+					format!(
+						"Couldn't find job `{}` for repository `{}`",
+						&id, &repository.slug
+					)
+					.into(),
+				)),
+			));
+		}
+	};
+
+	let previous_jobs = match queues_model.previous_on_branch(&job, FLAKY_TEST_HISTORY) {
+		Ok(previous_jobs) => previous_jobs,
+		Err(error) => {
+			error!("Unable to fetch previous jobs for {}. {}", &job.id, error);
+			return Err(Custom(
+				Status::InternalServerError,
+				Json(ErrorResponse::new("Unable to fetch previous jobs.".into())),
+			));
+		}
+	};
+
+	match TestResults::new(state.connection_manager.clone()).changes(&job, &previous_jobs) {
+		Ok(changes) => Ok(Json(changes)),
+		Err(error) => Err(Custom(
+			Status::InternalServerError,
+			Json(ErrorResponse::new(format!("{}", error))),
+		)),
+	}
+}
+
 #[get("/repositories/<repository>/jobs/<id>")]
 pub fn job(
 	repository: &RawStr,
@@ -691,6 +1381,88 @@ pub fn job(
 	}
 }
 
+#[post("/repositories/<repository>/jobs/<id>/approve")]
+pub fn approve_job(
+	repository: &RawStr,
+	id: &RawStr,
+	auth: AuthenticationPayload,
+	state: State<AppState>,
+) -> Result<Json<Response<QueueItem>>, Custom<Json<ErrorResponse>>> {
+	let user_payload: Option<UserPayload> = auth.into();
+	let username = user_payload.map(|user| user.username);
+
+	match state
+		.queue_manager
+		.approve(repository.as_str(), id.as_str(), username.as_ref().map(String::as_str))
+	{
+		Ok(job) => Ok(Json(Response { response: job })),
+		Err(error) => Err(Custom(
+			Status::BadRequest,
+			Json(ErrorResponse::new(format!("{}", error))),
+		)),
+	}
+}
+
+#[post("/repositories/<repository>/jobs/<id>/reject")]
+pub fn reject_job(
+	repository: &RawStr,
+	id: &RawStr,
+	auth: AuthenticationPayload,
+	state: State<AppState>,
+) -> Result<Json<Response<QueueItem>>, Custom<Json<ErrorResponse>>> {
+	let user_payload: Option<UserPayload> = auth.into();
+	let username = user_payload.map(|user| user.username);
+
+	match state
+		.queue_manager
+		.reject(repository.as_str(), id.as_str(), username.as_ref().map(String::as_str))
+	{
+		Ok(job) => Ok(Json(Response { response: job })),
+		Err(error) => Err(Custom(
+			Status::BadRequest,
+			Json(ErrorResponse::new(format!("{}", error))),
+		)),
+	}
+}
+
+#[get("/repositories/<repository>/builds/<id>")]
+pub fn build(
+	repository: &RawStr,
+	id: &RawStr,
+	_auth: AuthenticationPayload,
+	state: State<AppState>,
+) -> Result<Json<Response<Build>>, Custom<Json<ErrorResponse>>> {
+	let repository = repository.as_str();
+	let record = Repositories::new(state.connection_manager.clone()).find_by_slug(repository);
+	let repository = match record {
+		Some(repository) => repository,
+		None => {
+			return Err(Custom(
+				Status::NotFound,
+				Json(ErrorResponse::new(
+					format!("Repository `{}` does not exist", repository).into(),
+				)),
+			));
+		}
+	};
+
+	let id = id.as_str();
+
+	match Builds::new(state.connection_manager.clone()).find(&repository.id, &id) {
+		Ok(build) => Ok(Json(Response { response: build })),
+		Err(_) => Err(Custom(
+			Status::NotFound,
+			Json(ErrorResponse::new(
+				format!(
+					"Couldn't find build `{}` for repository `{}`",
+					&id, &repository.slug
+				)
+				.into(),
+			)),
+		)),
+	}
+}
+
 #[get("/static/<file..>")]
 pub fn get_static_asset(file: PathBuf) -> Assets {
 	Assets {
@@ -774,15 +1546,29 @@ pub fn start_server(app_state: AppState) -> Result<(), Error> {
 				notify,
 				notify_with_data,
 				notify_github,
+				notify_gitlab,
+				notify_gitea,
+				notify_git,
+				notify_webhook,
 				repositories,
 				repository,
 				add_repository,
 				update_repository,
 				delete_repository,
+				caches,
+				purge_caches,
+				purge_cache,
 				all_jobs,
 				jobs,
 				job,
 				log_output,
+				step_output,
+				job_summary,
+				job_test_results,
+				job_test_changes,
+				approve_job,
+				reject_job,
+				build,
 				login,
 				users,
 				get_user,
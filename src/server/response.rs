@@ -4,10 +4,14 @@ use std::collections::HashMap;
 use std::str;
 use std::sync::Arc;
 
-use crate::config::{AppConfig, Trigger};
+use crate::config::{
+	AppConfig, CacheEntry, InboundWebhook, Matrix, SkipRule, StatusReporting, Step, Trigger,
+	Upstream,
+};
 use crate::model::repositories::Repository;
 use crate::model::users::User;
-use crate::util::serialize_date;
+use crate::queue::scheduler::next_scheduled_run;
+use crate::util::{serialize_date, serialize_optional_date};
 
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
@@ -60,6 +64,23 @@ pub struct RepositoryResponse {
 	pub variables: HashMap<String, String>,
 	pub triggers: Vec<Trigger>,
 	pub webhooks: Vec<String>,
+	pub test_reports: Vec<String>,
+	pub caches: Vec<CacheEntry>,
+	pub matrix: Option<Matrix>,
+	pub upstream: Vec<Upstream>,
+	pub steps: Vec<Step>,
+	pub after_success: Option<String>,
+	pub after_failure: Option<String>,
+	pub always: Option<String>,
+	pub requires_approval: bool,
+	pub approval_timeout: Option<i32>,
+	pub skip_rules: Vec<SkipRule>,
+	pub inbound: Option<InboundWebhook>,
+	pub status_reporting: Option<StatusReporting>,
+	pub idempotency_variable: Option<String>,
+	/// When the next job will be queued by a schedule trigger
+	#[serde(serialize_with = "serialize_optional_date")]
+	pub next_scheduled_run: Option<NaiveDateTime>,
 	pub secret: String,
 }
 
@@ -68,6 +89,7 @@ impl From<Repository> for RepositoryResponse {
 		Self {
 			id: repository.id,
 			slug: repository.slug,
+			next_scheduled_run: next_scheduled_run(&repository.triggers),
 			name: repository.name,
 			run: repository.run,
 			working_dir: repository.working_dir,
@@ -75,6 +97,24 @@ impl From<Repository> for RepositoryResponse {
 			variables: repository.variables,
 			triggers: repository.triggers,
 			webhooks: repository.webhooks,
+			test_reports: repository.test_reports,
+			caches: repository.caches,
+			matrix: repository.matrix,
+			upstream: repository.upstream,
+			steps: repository.steps,
+			after_success: repository.after_success,
+			after_failure: repository.after_failure,
+			always: repository.always,
+			requires_approval: repository.requires_approval,
+			approval_timeout: repository.approval_timeout,
+			skip_rules: repository.skip_rules,
+			inbound: repository.inbound,
+			// The token is write-only. An empty token keeps the stored one when saving.
+			status_reporting: repository.status_reporting.map(|settings| StatusReporting {
+				token: String::new(),
+				..settings
+			}),
+			idempotency_variable: repository.idempotency_variable,
 		}
 	}
 }
@@ -0,0 +1,64 @@
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::Sha256;
+
+use crate::config::SignatureAlgorithm;
+
+type HmacSha1 = Hmac<Sha1>;
+type HmacSha256 = Hmac<Sha256>;
+
+/// Whether `signature` is the HMAC of `body` keyed with `key`. The comparison is constant-time.
+pub fn verify_hmac(
+	algorithm: SignatureAlgorithm,
+	key: &[u8],
+	signature: &[u8],
+	body: &[u8],
+) -> bool {
+	match algorithm {
+		SignatureAlgorithm::Sha1 => match HmacSha1::new_varkey(key) {
+			Ok(mut mac) => {
+				mac.input(body);
+				mac.verify(signature).is_ok()
+			}
+			Err(_) => false,
+		},
+		SignatureAlgorithm::Sha256 => match HmacSha256::new_varkey(key) {
+			Ok(mut mac) => {
+				mac.input(body);
+				mac.verify(signature).is_ok()
+			}
+			Err(_) => false,
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const BODY: &[u8] = b"The quick brown fox jumps over the lazy dog";
+	const SHA1: &str = "de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9";
+	const SHA256: &str = "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8";
+
+	fn verify(algorithm: SignatureAlgorithm, key: &str, signature: &str, body: &[u8]) -> bool {
+		let signature = hex::decode(signature).unwrap();
+		verify_hmac(algorithm, key.as_bytes(), &signature, body)
+	}
+
+	#[test]
+	fn verify_hmac_accepts_valid_signatures() {
+		assert!(verify(SignatureAlgorithm::Sha1, "key", SHA1, BODY));
+		assert!(verify(SignatureAlgorithm::Sha256, "key", SHA256, BODY));
+	}
+
+	#[test]
+	fn verify_hmac_rejects_invalid_signatures() {
+		let sha256 = SignatureAlgorithm::Sha256;
+		assert!(!verify(sha256, "other key", SHA256, BODY));
+		assert!(!verify(sha256, "key", SHA256, b"tampered"));
+		assert!(!verify(sha256, "key", SHA1, BODY));
+		assert!(!verify(sha256, "key", &SHA256[..32], BODY));
+		assert!(!verify(sha256, "key", "", BODY));
+		assert!(!verify(SignatureAlgorithm::Sha1, "key", SHA256, BODY));
+	}
+}
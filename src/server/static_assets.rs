@@ -36,13 +36,21 @@ impl Responder<'_> for Assets {
 			AssetType::UI => UI::get,
 		};
 
-		if let Some(asset) = get_fn(self.file_path.to_str().unwrap()) {
+		let mut file_path = self.file_path;
+		// The UI routes in history mode, so its pages, eg. links to jobs, are served by index.html
+		if let AssetType::UI = self.asset_type {
+			if file_path.extension().is_none() && get_fn(file_path.to_str().unwrap()).is_none() {
+				file_path = PathBuf::from("index.html");
+			}
+		}
+
+		if let Some(asset) = get_fn(file_path.to_str().unwrap()) {
 			let data = asset.as_ref().to_vec();
 
 			let stream_response = rocket::response::Stream::chunked(Cursor::new(data), 10);
 			let mut response = stream_response.respond_to(&req)?;
 
-			if let Some(extension) = self.file_path.extension() {
+			if let Some(extension) = file_path.extension() {
 				if let Some(content_type) =
 					ContentType::from_extension(&extension.to_string_lossy())
 				{
@@ -0,0 +1,50 @@
+use pulldown_cmark::{html, Options, Parser};
+use rocket::http::ContentType;
+use rocket::FromFormValue;
+
+/// Format used when rendering a job's summary.
+#[derive(FromFormValue, Debug, Clone, Copy, PartialEq)]
+pub enum SummaryFormat {
+	/// Markdown as written by the job
+	Markdown,
+	/// Markdown rendered to sanitized HTML
+	Html,
+}
+
+impl Default for SummaryFormat {
+	fn default() -> Self {
+		Self::Markdown
+	}
+}
+
+impl SummaryFormat {
+	pub fn content_type(&self) -> ContentType {
+		match self {
+			SummaryFormat::Markdown => ContentType::new("text", "markdown"),
+			SummaryFormat::Html => ContentType::HTML,
+		}
+	}
+}
+
+/// Render markdown to HTML. Any raw HTML, scripts or unsafe attributes in the rendered output are
+/// removed since the summary is written by the job and can't be trusted.
+pub fn to_html(markdown: &str) -> String {
+	let mut options = Options::empty();
+	options.insert(Options::ENABLE_TABLES);
+	options.insert(Options::ENABLE_STRIKETHROUGH);
+	options.insert(Options::ENABLE_TASKLISTS);
+
+	let parser = Parser::new_ext(markdown, options);
+	let mut unsafe_html = String::new();
+	html::push_html(&mut unsafe_html, parser);
+
+	ammonia::clean(&unsafe_html)
+}
+
+/// Render a summary in the requested format.
+pub fn render(markdown: String, format: SummaryFormat) -> String {
+	match format {
+		SummaryFormat::Markdown => markdown,
+		SummaryFormat::Html => to_html(&markdown),
+	}
+}
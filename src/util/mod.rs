@@ -11,6 +11,16 @@ where
 	s.serialize_str(&formatted)
 }
 
+pub fn serialize_optional_date<S>(dt: &Option<NaiveDateTime>, s: S) -> Result<S::Ok, S::Error>
+where
+	S: Serializer,
+{
+	match dt {
+		Some(dt) => serialize_date(dt, s),
+		None => s.serialize_none(),
+	}
+}
+
 pub fn utc_now() -> NaiveDateTime {
 	Utc::now().naive_utc()
 }
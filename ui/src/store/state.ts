@@ -33,6 +33,7 @@ export interface Repository {
 	working_dir?: string,
 	variables?: {},
 	triggers?: [],
+	next_scheduled_run?: Date,
 	secret?: string,
 }
 
@@ -42,11 +43,23 @@ export interface Job {
 	status: string,
 	exit_code: number,
 	data: object,
+	outputs: { [key: string]: string },
+	build_id?: string,
+	matrix: { [key: string]: string },
+	upstream_job_id?: string,
+	steps: Step[],
 	created_at: Date,
 	updated_at: Date,
 	logs: Log[],
 }
 
+export interface Step {
+	name: string,
+	needs: string[],
+	status: string,
+	exit_code: number,
+}
+
 export interface JobSummary {
 	id: string,
 	status: string,
@@ -59,6 +72,7 @@ export interface JobSummary {
 export interface Log {
 	status: string,
 	exit_code: number,
+	username?: string,
 	created_at: Date,
 }
 
@@ -410,12 +424,54 @@ export default class State {
 		return await response.json()
 	}
 
-	@action.bound async getJobOutput(repository: string, jobId: string): Promise<string> {
+	@action.bound async approveJob(repository: string, jobId: string): Promise<Job> {
+		if (!this.user) {
+			throw new Error('Not logged in')
+		}
+
+		const response = await fetch(`${baseUrl}/repositories/${repository}/jobs/${jobId}/approve`, {
+			method: 'POST',
+			headers: {
+				'Content-Type': 'application/json',
+				'Authorization': `Bearer ${this.user.token}`,
+			},
+		})
+
+		if (!response.ok) {
+			const responseObject: ErrorResponse = await response.json()
+			throw new Error(responseObject.message)
+		}
+
+		return await response.json()
+	}
+
+	@action.bound async rejectJob(repository: string, jobId: string): Promise<Job> {
+		if (!this.user) {
+			throw new Error('Not logged in')
+		}
+
+		const response = await fetch(`${baseUrl}/repositories/${repository}/jobs/${jobId}/reject`, {
+			method: 'POST',
+			headers: {
+				'Content-Type': 'application/json',
+				'Authorization': `Bearer ${this.user.token}`,
+			},
+		})
+
+		if (!response.ok) {
+			const responseObject: ErrorResponse = await response.json()
+			throw new Error(responseObject.message)
+		}
+
+		return await response.json()
+	}
+
+	@action.bound async getJobOutput(repository: string, jobId: string, format: string = 'html'): Promise<string> {
 		if (!this.user) {
 			throw new Error('Not logged in')
 		}
 
-		const response = await fetch(`${baseUrl}/repositories/${repository}/jobs/${jobId}/output`, {
+		const response = await fetch(`${baseUrl}/repositories/${repository}/jobs/${jobId}/output?format=${format}`, {
 			method: 'GET',
 			headers: {
 				'Content-Type': 'application/json',